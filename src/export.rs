@@ -0,0 +1,410 @@
+use std::{
+    error::Error,
+    io::{self, BufWriter, Write},
+};
+
+use serde::{Deserialize, Serialize};
+use tantivy::{query::Query, Document};
+use zip::{
+    write::{SimpleFileOptions, StreamWriter},
+    CompressionMethod, ZipWriter,
+};
+
+use crate::{
+    highlight::{Highlighter, MatchedCell},
+    index_tantivy::FileSearchIndex,
+};
+
+/// Rows of a worksheet, the first one being the header row.
+const MAX_XLSX_ROWS: usize = 1_048_576;
+/// Characters of an xlsx cell.
+const MAX_XLSX_CELL_CHARS: usize = 32_767;
+const EXPORT_HEADERS: [&str; 5] = ["file", "sheet", "position", "header", "value"];
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+    Xlsx,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Xlsx => {
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            }
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Xlsx => "xlsx",
+        }
+    }
+}
+
+/// A cell of a hit, hits without matched cells, e.g. pdf passages, export all their
+/// values.
+#[derive(Serialize)]
+pub struct ExportRow<'a> {
+    pub file: &'a str,
+    pub sheet: &'a str,
+    pub position: &'a str,
+    pub header: &'a str,
+    pub value: &'a str,
+}
+
+impl ExportRow<'_> {
+    fn values(&self) -> [&str; 5] {
+        [
+            self.file,
+            self.sheet,
+            self.position,
+            self.header,
+            self.value,
+        ]
+    }
+}
+
+trait RowWriter {
+    fn write_row(&mut self, row: &ExportRow) -> io::Result<()>;
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// Writes every hit of the query to `out`, returns the number of hits.
+pub fn export<'a, W: Write + 'a>(
+    fsi: &FileSearchIndex,
+    query: &dyn Query,
+    highlighter: &Highlighter,
+    format: ExportFormat,
+    out: W,
+) -> Result<u64, Box<dyn Error>> {
+    let mut writer: Box<dyn RowWriter + 'a> = match format {
+        ExportFormat::Csv => Box::new(CsvRowWriter::new(out)?),
+        ExportFormat::Ndjson => Box::new(BufWriter::new(out)),
+        ExportFormat::Xlsx => Box::new(XlsxRowWriter::new(out)?),
+    };
+    let count = fsi.for_each_hit(query, highlighter, |doc, matched_cells| {
+        write_hit(fsi, writer.as_mut(), doc, matched_cells)
+    })?;
+    writer.finish()?;
+    Ok(count)
+}
+
+fn write_hit(
+    fsi: &FileSearchIndex,
+    writer: &mut dyn RowWriter,
+    doc: &Document,
+    matched_cells: Vec<MatchedCell>,
+) -> io::Result<()> {
+    let text = |field| {
+        doc.get_first(field)
+            .and_then(|v| v.as_text())
+            .unwrap_or_default()
+    };
+    let texts = |field| doc.get_all(field).flat_map(|v| v.as_text());
+    let file = text(fsi.file_path_field);
+    let sheet = text(fsi.sheet_name_field);
+    if matched_cells.is_empty() {
+        let mut positions = texts(fsi.cell_position_field);
+        let mut headers = texts(fsi.cell_ctx_field);
+        for value in texts(fsi.cell_value_field) {
+            writer.write_row(&ExportRow {
+                file,
+                sheet,
+                position: positions.next().unwrap_or_default(),
+                header: headers.next().unwrap_or_default(),
+                value,
+            })?;
+        }
+        return Ok(());
+    }
+    for cell in &matched_cells {
+        writer.write_row(&ExportRow {
+            file,
+            sheet,
+            position: &cell.position,
+            header: &cell.header,
+            value: &cell.value,
+        })?;
+    }
+    Ok(())
+}
+
+struct CsvRowWriter<W: Write>(csv::Writer<W>);
+
+impl<W: Write> CsvRowWriter<W> {
+    fn new(out: W) -> io::Result<Self> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(EXPORT_HEADERS)?;
+        Ok(CsvRowWriter(writer))
+    }
+}
+
+impl<W: Write> RowWriter for CsvRowWriter<W> {
+    fn write_row(&mut self, row: &ExportRow) -> io::Result<()> {
+        Ok(self.0.write_record(row.values())?)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> RowWriter for BufWriter<W> {
+    fn write_row(&mut self, row: &ExportRow) -> io::Result<()> {
+        serde_json::to_writer(&mut *self, row)?;
+        self.write_all(b"\n")
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+/// Streams the workbook, each part of the archive is followed by its sizes rather than
+/// preceded by them. Hits beyond the rows of a sheet go to the next one.
+struct XlsxRowWriter<W: Write> {
+    zip: ZipWriter<StreamWriter<BufWriter<W>>>,
+    sheets: usize,
+    rows: usize,
+}
+
+impl<W: Write> XlsxRowWriter<W> {
+    fn new(out: W) -> io::Result<Self> {
+        let mut writer = XlsxRowWriter {
+            zip: ZipWriter::new_stream(BufWriter::new(out)),
+            sheets: 0,
+            rows: 0,
+        };
+        writer.start_sheet()?;
+        Ok(writer)
+    }
+
+    fn start_sheet(&mut self) -> io::Result<()> {
+        if self.sheets > 0 {
+            self.zip.write_all(b"</sheetData></worksheet>")?;
+        }
+        self.sheets += 1;
+        self.rows = 0;
+        self.zip.start_file(
+            format!("xl/worksheets/sheet{}.xml", self.sheets),
+            SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+        )?;
+        self.zip.write_all(
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+                "<sheetData>"
+            )
+            .as_bytes(),
+        )?;
+        self.write_cells(EXPORT_HEADERS)
+    }
+
+    fn write_cells(&mut self, values: [&str; 5]) -> io::Result<()> {
+        self.rows += 1;
+        write!(self.zip, r#"<row r="{}">"#, self.rows)?;
+        for value in values {
+            write!(
+                self.zip,
+                r#"<c t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                xml_escape(value)
+            )?;
+        }
+        self.zip.write_all(b"</row>")
+    }
+
+    fn write_part(&mut self, name: &str, xml: &str) -> io::Result<()> {
+        self.zip.start_file(
+            name,
+            SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+        )?;
+        self.zip
+            .write_all(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#.as_bytes())?;
+        self.zip.write_all(xml.as_bytes())
+    }
+}
+
+impl<W: Write> RowWriter for XlsxRowWriter<W> {
+    fn write_row(&mut self, row: &ExportRow) -> io::Result<()> {
+        if self.rows == MAX_XLSX_ROWS {
+            self.start_sheet()?;
+        }
+        self.write_cells(row.values())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.zip.write_all(b"</sheetData></worksheet>")?;
+        let sheets = 1..=self.sheets;
+        let content_types = sheets
+            .clone()
+            .map(|n| {
+                format!(
+                    r#"<Override PartName="/xl/worksheets/sheet{n}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#
+                )
+            })
+            .collect::<String>();
+        self.write_part(
+            "[Content_Types].xml",
+            &format!(
+                concat!(
+                    r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+                    r#"<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>"#,
+                    r#"<Default Extension="xml" ContentType="application/xml"/>"#,
+                    r#"<Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>"#,
+                    "{}</Types>"
+                ),
+                content_types
+            ),
+        )?;
+        self.write_part(
+            "_rels/.rels",
+            concat!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/>"#,
+                "</Relationships>"
+            ),
+        )?;
+        let sheet_entries = sheets
+            .clone()
+            .map(|n| format!(r#"<sheet name="Hits {n}" sheetId="{n}" r:id="rId{n}"/>"#))
+            .collect::<String>();
+        self.write_part(
+            "xl/workbook.xml",
+            &format!(
+                concat!(
+                    r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" "#,
+                    r#"xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">"#,
+                    "<sheets>{}</sheets></workbook>"
+                ),
+                sheet_entries
+            ),
+        )?;
+        let sheet_relationships = sheets
+            .map(|n| {
+                format!(
+                    r#"<Relationship Id="rId{n}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{n}.xml"/>"#
+                )
+            })
+            .collect::<String>();
+        self.write_part(
+            "xl/_rels/workbook.xml.rels",
+            &format!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{}</Relationships>"#,
+                sheet_relationships
+            ),
+        )?;
+        self.zip.finish()?.into_inner().flush()
+    }
+}
+
+/// Escapes a cell value, truncated to the characters of a cell, dropping the control
+/// characters xml can't represent.
+fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars().take(MAX_XLSX_CELL_CHARS) {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use calamine::{DataType, Reader, Xlsx};
+
+    use super::{export, ExportFormat};
+    use crate::{
+        index_csv::index_csv_file,
+        index_tantivy::{QueryType, SearchOptions},
+        test_utils::test_index,
+    };
+
+    #[tokio::test]
+    async fn test_export() {
+        let fsi = test_index();
+        let dir = &fsi.dir;
+        let file = dir.join("invoices.csv");
+        std::fs::write(
+            &file,
+            "Supplier,Note\nacme,\"a <b> & \"\"c\"\"\"\nglobex,acme\n",
+        )
+        .unwrap();
+        index_csv_file(fsi.clone(), &file, None).await.unwrap();
+        fsi.index_reader.reload().unwrap();
+        let file = file.canonicalize().unwrap().to_string_lossy().to_string();
+
+        let (query, highlighter) = fsi
+            .prepare_search("acme", &QueryType::TermQuery, &SearchOptions::default())
+            .unwrap();
+        let exported = |format| {
+            let mut out = vec![];
+            let count = export(&fsi, query.as_ref(), &highlighter, format, &mut out).unwrap();
+            assert_eq!(2, count);
+            out
+        };
+
+        let csv = String::from_utf8(exported(ExportFormat::Csv)).unwrap();
+        assert_eq!(
+            format!(
+                "file,sheet,position,header,value\n{file},Sheet1,A2,Supplier,acme\n{file},Sheet1,B3,Note,acme\n"
+            ),
+            csv
+        );
+
+        let ndjson = String::from_utf8(exported(ExportFormat::Ndjson)).unwrap();
+        let rows = ndjson
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(2, rows.len());
+        assert_eq!("B3", rows[1]["position"]);
+        assert_eq!("Note", rows[1]["header"]);
+
+        let xlsx = exported(ExportFormat::Xlsx);
+        let mut workbook = Xlsx::new(Cursor::new(xlsx)).unwrap();
+        let range = workbook.worksheet_range("Hits 1").unwrap().unwrap();
+        assert_eq!((3, 5), range.get_size());
+        assert_eq!(
+            Some(&DataType::String("value".to_string())),
+            range.get((0, 4))
+        );
+        assert_eq!(Some(&DataType::String("A2".to_string())), range.get((1, 2)));
+
+        // cells are escaped
+        let (query, highlighter) = fsi
+            .prepare_search("b", &QueryType::TermQuery, &SearchOptions::default())
+            .unwrap();
+        let mut out = vec![];
+        export(
+            &fsi,
+            query.as_ref(),
+            &highlighter,
+            ExportFormat::Xlsx,
+            &mut out,
+        )
+        .unwrap();
+        let mut workbook = Xlsx::new(Cursor::new(out)).unwrap();
+        let range = workbook.worksheet_range("Hits 1").unwrap().unwrap();
+        assert_eq!(
+            Some(&DataType::String("a <b> & \"c\"".to_string())),
+            range.get((1, 4))
+        );
+    }
+}
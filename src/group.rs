@@ -0,0 +1,184 @@
+use std::{cmp::Ordering, collections::HashMap, io};
+
+use serde::Deserialize;
+use tantivy::{
+    collector::{Collector, SegmentCollector},
+    columnar::StrColumn,
+    DocAddress, DocId, Score, SegmentOrdinal, SegmentReader, TantivyError,
+};
+
+use crate::{
+    index_tantivy::{FILE_PATH_FIELD, SHEET_NAME_FIELD},
+    utils::{keep_first, push_bounded},
+};
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    File,
+    Sheet,
+}
+
+/// Groups the hits of a search by source.
+#[derive(Clone, Copy)]
+pub struct GroupOptions {
+    pub by: GroupBy,
+    /// Best hits returned per group.
+    pub hits_per_group: usize,
+}
+
+/// Source of a group of hits: a file, or a sheet of a file.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GroupKey {
+    pub file_path: String,
+    pub sheet: Option<String>,
+}
+
+pub struct GroupFruit {
+    pub key: GroupKey,
+    pub total_hits: u64,
+    pub best_score: Score,
+    /// Best hits first.
+    pub top_docs: Vec<(Score, DocAddress)>,
+}
+
+/// Collects every matching document by source, counting them and keeping the best ones.
+/// Groups are sorted by their best score, then by source.
+pub struct GroupCollector {
+    pub options: GroupOptions,
+}
+
+impl Collector for GroupCollector {
+    type Fruit = Vec<GroupFruit>;
+    type Child = GroupSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        reader: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let fast_fields = reader.fast_fields();
+        let file_paths = fast_fields.str(FILE_PATH_FIELD)?.ok_or_else(|| {
+            TantivyError::SchemaError(format!("{FILE_PATH_FIELD} is not a fast field"))
+        })?;
+        let sheets = match self.options.by {
+            GroupBy::File => None,
+            GroupBy::Sheet => fast_fields.str(SHEET_NAME_FIELD)?,
+        };
+        Ok(GroupSegmentCollector {
+            segment_ord,
+            file_paths,
+            sheets,
+            hits_per_group: self.options.hits_per_group,
+            groups: HashMap::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(
+        &self,
+        fruits: Vec<io::Result<Vec<GroupFruit>>>,
+    ) -> tantivy::Result<Self::Fruit> {
+        let mut groups: HashMap<GroupKey, GroupFruit> = HashMap::new();
+        for fruit in fruits {
+            for group in fruit? {
+                match groups.get_mut(&group.key) {
+                    Some(merged) => {
+                        merged.total_hits += group.total_hits;
+                        merged.best_score = merged.best_score.max(group.best_score);
+                        merged.top_docs.extend(group.top_docs);
+                        keep_first(&mut merged.top_docs, self.options.hits_per_group, by_score);
+                    }
+                    None => {
+                        groups.insert(group.key.clone(), group);
+                    }
+                }
+            }
+        }
+        let mut groups: Vec<GroupFruit> = groups.into_values().collect();
+        groups.sort_by(|a, b| {
+            b.best_score
+                .total_cmp(&a.best_score)
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        Ok(groups)
+    }
+}
+
+#[derive(Default)]
+struct SegmentGroup {
+    total_hits: u64,
+    best_score: Score,
+    top_docs: Vec<(Score, DocId)>,
+}
+
+pub struct GroupSegmentCollector {
+    segment_ord: SegmentOrdinal,
+    file_paths: StrColumn,
+    sheets: Option<StrColumn>,
+    hits_per_group: usize,
+    /// Groups by term ordinals of the file path and sheet name in this segment.
+    groups: HashMap<(u64, Option<u64>), SegmentGroup>,
+}
+
+impl SegmentCollector for GroupSegmentCollector {
+    type Fruit = io::Result<Vec<GroupFruit>>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let Some(file_ord) = self.file_paths.term_ords(doc).next() else {
+            return;
+        };
+        let sheet_ord = self
+            .sheets
+            .as_ref()
+            .and_then(|sheets| sheets.term_ords(doc).next());
+        let group = self.groups.entry((file_ord, sheet_ord)).or_default();
+        group.total_hits += 1;
+        group.best_score = group.best_score.max(score);
+        push_bounded(
+            &mut group.top_docs,
+            (score, doc),
+            self.hits_per_group,
+            by_score,
+        );
+    }
+
+    fn harvest(self) -> Self::Fruit {
+        let mut fruits = Vec::with_capacity(self.groups.len());
+        for ((file_ord, sheet_ord), mut group) in self.groups {
+            let mut file_path = String::new();
+            self.file_paths.ord_to_str(file_ord, &mut file_path)?;
+            let sheet = match (&self.sheets, sheet_ord) {
+                (Some(sheets), Some(sheet_ord)) => {
+                    let mut sheet = String::new();
+                    sheets.ord_to_str(sheet_ord, &mut sheet)?;
+                    Some(sheet)
+                }
+                _ => None,
+            };
+            keep_first(&mut group.top_docs, self.hits_per_group, by_score);
+            fruits.push(GroupFruit {
+                key: GroupKey { file_path, sheet },
+                total_hits: group.total_hits,
+                best_score: group.best_score,
+                top_docs: group
+                    .top_docs
+                    .into_iter()
+                    .map(|(score, doc)| (score, DocAddress::new(self.segment_ord, doc)))
+                    .collect(),
+            });
+        }
+        Ok(fruits)
+    }
+}
+
+/// Descending score, ties by document.
+fn by_score<D: Ord>(a: &(Score, D), b: &(Score, D)) -> Ordering {
+    match b.0.total_cmp(&a.0) {
+        Ordering::Equal => a.1.cmp(&b.1),
+        ordering => ordering,
+    }
+}
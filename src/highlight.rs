@@ -0,0 +1,265 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    ops::Range,
+};
+
+use regex::Regex;
+use serde::Serialize;
+use tantivy::{
+    query::Query, schema::Field, tokenizer::TextAnalyzer, Document, Score, Searcher,
+    SnippetGenerator, Term,
+};
+
+use crate::index_tantivy::{term_text, FileSearchIndex, FuzzyOptions, QueryType};
+
+pub const DEFAULT_SNIPPET_MAX_CHARS: usize = 150;
+
+#[derive(Serialize)]
+pub struct HitSnippet {
+    /// Fragment of the matching text.
+    pub fragment: String,
+    /// Byte offsets of the matches within the fragment.
+    pub highlighted: Vec<Range<usize>>,
+    /// Fragment with the matches wrapped in `<mark>` tags, html escaped.
+    pub html: String,
+}
+
+/// Tells whether a token of the indexed text was matched by the query.
+pub enum TermMatcher {
+    Terms(BTreeSet<String>),
+    Fuzzy {
+        terms: Vec<String>,
+        options: FuzzyOptions,
+    },
+    Regex(Regex),
+}
+
+impl TermMatcher {
+    /// Matcher of a term, fuzzy or regex query on `field`. The terms of a parsed query are
+    /// only known once it is parsed, `None` is returned for the query parser.
+    pub fn for_query(
+        fsi: &FileSearchIndex,
+        field: Field,
+        q: &str,
+        query_type: &QueryType,
+        fuzzy: &FuzzyOptions,
+    ) -> Result<Option<TermMatcher>, Box<dyn Error>> {
+        Ok(match query_type {
+            QueryType::TermQuery => Some(TermMatcher::Terms(BTreeSet::from([q.to_string()]))),
+            QueryType::FuzzySearch => Some(TermMatcher::Fuzzy {
+                terms: fsi.tokenize(field, q)?,
+                options: *fuzzy,
+            }),
+            // tantivy matches the regex against the whole term
+            QueryType::RegexQuery => Some(TermMatcher::Regex(Regex::new(&format!("^(?:{q})$"))?)),
+            QueryType::QueryParser => None,
+        })
+    }
+
+    pub fn matches(&self, token: &str) -> bool {
+        match self {
+            TermMatcher::Terms(terms) => terms.contains(token),
+            TermMatcher::Fuzzy { terms, options } => {
+                terms.iter().any(|term| options.matches(term, token))
+            }
+            TermMatcher::Regex(regex) => regex.is_match(token),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct MatchedCell {
+    pub position: String,
+    pub header: String,
+    pub value: String,
+}
+
+/// Tells why a hit matched: snippets and matched spreadsheet cells. Fuzzy and regex
+/// queries do not expose the terms they matched, so the tokens of each hit are matched
+/// against the query again.
+pub struct Highlighter {
+    matcher: TermMatcher,
+    /// Stored text of the hits.
+    field: Field,
+    /// Field the query was run on, the stored text is analyzed like it.
+    analyzed_field: Field,
+    tokenizer: TextAnalyzer,
+    header: Option<String>,
+}
+
+impl Highlighter {
+    /// Without `matcher`, the terms of the query on `analyzed_field` are highlighted.
+    /// `header` restricts the matched cells to a column.
+    pub fn new(
+        fsi: &FileSearchIndex,
+        analyzed_field: Field,
+        matcher: Option<TermMatcher>,
+        query: &dyn Query,
+        header: Option<&str>,
+    ) -> tantivy::Result<Highlighter> {
+        let matcher = match matcher {
+            Some(matcher) => matcher,
+            None => {
+                let mut terms = BTreeSet::new();
+                query.query_terms(&mut |term, _| {
+                    if term.field() == analyzed_field || term.field() == fsi.row_field {
+                        if let Some(text) = term_text(term) {
+                            terms.insert(text.to_string());
+                        }
+                    }
+                });
+                TermMatcher::Terms(terms)
+            }
+        };
+        let tokenizer = fsi.index.tokenizer_for_field(analyzed_field)?;
+        Ok(Highlighter {
+            matcher,
+            field: fsi.cell_value_field,
+            analyzed_field,
+            tokenizer,
+            header: header.map(String::from),
+        })
+    }
+
+    fn matches_text(&self, tokenizer: &mut TextAnalyzer, text: &str) -> bool {
+        let mut token_stream = tokenizer.token_stream(text);
+        while let Some(token) = token_stream.next() {
+            if self.matcher.matches(&token.text) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Cells of a spreadsheet row whose value matched. A row stores the position, header
+    /// and value of each non empty cell in the same order, in three multi-valued fields.
+    pub fn matched_cells(
+        &self,
+        doc: &Document,
+        position_field: Field,
+        header_field: Field,
+    ) -> Vec<MatchedCell> {
+        let mut tokenizer = self.tokenizer.clone();
+        let text_values = |field| doc.get_all(field).flat_map(|v| v.as_text());
+        text_values(position_field)
+            .zip(text_values(header_field))
+            .zip(text_values(self.field))
+            .filter(|((_, header), _)| self.header.as_deref().is_none_or(|h| h == *header))
+            .filter(|(_, value)| self.matches_text(&mut tokenizer, value))
+            .map(|((position, header), value)| MatchedCell {
+                position: position.to_string(),
+                header: header.to_string(),
+                value: value.to_string(),
+            })
+            .collect()
+    }
+
+    pub fn snippet(
+        &self,
+        searcher: &Searcher,
+        doc: &Document,
+        max_chars: usize,
+    ) -> tantivy::Result<HitSnippet> {
+        let mut tokenizer = self.tokenizer.clone();
+        let mut terms_text: BTreeMap<String, Score> = BTreeMap::new();
+        let texts: Vec<&str> = doc.get_all(self.field).flat_map(|v| v.as_text()).collect();
+        for text in &texts {
+            let mut token_stream = tokenizer.token_stream(text);
+            while let Some(token) = token_stream.next() {
+                if terms_text.contains_key(&token.text) || !self.matcher.matches(&token.text) {
+                    continue;
+                }
+                let doc_freq =
+                    searcher.doc_freq(&Term::from_field_text(self.analyzed_field, &token.text))?;
+                terms_text.insert(token.text.clone(), 1.0 / (1.0 + doc_freq as Score));
+            }
+        }
+        let snippet_generator = SnippetGenerator::new(terms_text, tokenizer, self.field, max_chars);
+        let mut snippet = snippet_generator.snippet(&texts.join(" "));
+        snippet.set_snippet_prefix_postfix("<mark>", "</mark>");
+        Ok(HitSnippet {
+            fragment: snippet.fragment().to_string(),
+            highlighted: snippet.highlighted().to_vec(),
+            html: snippet.to_html(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tantivy::Document;
+
+    use crate::{
+        index_tantivy::{QueryType, SearchOptions},
+        test_utils::{metadata, test_index},
+    };
+
+    #[tokio::test]
+    async fn test_snippets() {
+        let fsi = test_index();
+        let mut doc = Document::default();
+        doc.add_text(
+            fsi.cell_value_field,
+            "Worked as a software engineer in Brussels & Namur",
+        );
+        fsi.replace_documents(&metadata("/data/cv.pdf"), vec![doc])
+            .await
+            .unwrap();
+        fsi.index_reader.reload().unwrap();
+
+        for (q, query_type, html) in [
+            ("brussels", QueryType::TermQuery, "<mark>Brussels</mark>"),
+            ("enginer", QueryType::FuzzySearch, "<mark>engineer</mark>"),
+            ("soft.*", QueryType::RegexQuery, "<mark>software</mark>"),
+            (
+                "namur OR brussels",
+                QueryType::QueryParser,
+                "<mark>Brussels</mark> &amp; <mark>Namur</mark>",
+            ),
+        ] {
+            let hits = fsi
+                .search(
+                    0,
+                    10,
+                    q,
+                    &query_type,
+                    &SearchOptions {
+                        snippet_max_chars: Some(150),
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            let snippet = hits.hits[0].snippet.as_ref().unwrap();
+            assert!(snippet.html.contains(html), "{q}: {}", snippet.html);
+        }
+
+        let hits = fsi
+            .search(
+                0,
+                10,
+                "engineer",
+                &QueryType::TermQuery,
+                &SearchOptions {
+                    snippet_max_chars: Some(20),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let snippet = hits.hits[0].snippet.as_ref().unwrap();
+        assert!(snippet.fragment.chars().count() <= 20);
+        let range = snippet.highlighted[0].clone();
+        assert_eq!("engineer", &snippet.fragment[range]);
+
+        let hits = fsi
+            .search(
+                0,
+                10,
+                "engineer",
+                &QueryType::TermQuery,
+                &SearchOptions::default(),
+            )
+            .unwrap();
+        assert!(hits.hits[0].snippet.is_none());
+    }
+}
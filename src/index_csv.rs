@@ -1,22 +1,30 @@
 use std::{error::Error, path::PathBuf};
 
+use serde_json::Map;
 use tantivy::Document;
 
-use crate::{index_tantivy::FileSearchIndex, utils::convert_row_column_to_letter};
+use crate::{
+    index_tantivy::{FileMetadata, FileSearchIndex},
+    language::DocLanguage,
+    utils::{column_header, convert_row_column_to_letter, insert_cell, CellValue},
+};
 
 pub async fn index_csv_file(
     file_search_index: FileSearchIndex,
     path_to_csv: impl Into<PathBuf>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let path = path_to_csv.into();
+    language: Option<&'static DocLanguage>,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let path = path_to_csv.into().canonicalize()?;
+    let metadata = FileMetadata::from_path(&path)?;
     tracing::info!("indexing start for csv {path:?}.");
     let mut rdr = csv::ReaderBuilder::new()
         .has_headers(false) // neededd
+        .flexible(true) // rows may have more cells than the header
         .from_path(&path)?;
     let records: Vec<_> = rdr.records().filter_map(|r| r.ok()).collect();
     if records.len() < 2 {
         tracing::info!("not enough row to index...");
-        return Ok(());
+        return file_search_index.replace_documents(&metadata, vec![]).await;
     }
 
     let labels: Vec<_> = records
@@ -24,18 +32,17 @@ pub async fn index_csv_file(
         .take(1)
         .flat_map(|c| c.iter().map(|x| x.to_string()))
         .collect();
-    let mut index_writer = file_search_index.index_writer.lock().await;
+    let mut docs = Vec::with_capacity(records.len());
 
     for (row_idx, row) in records.iter().skip(1).enumerate() {
         if row.iter().all(|c| c.trim().is_empty()) {
             continue;
         }
         let mut doc = Document::default();
-        doc.add_text(
-            file_search_index.file_name_field,
-            path.file_name().ok_or("file not found")?.to_string_lossy(),
-        );
         doc.add_text(file_search_index.sheet_name_field, "Sheet1");
+        doc.add_u64(file_search_index.row_number_field, row_idx as u64 + 2);
+        let mut row_object = Map::new();
+        let mut row_values = Map::new();
         for (column, cell) in row.iter().enumerate() {
             let cell = cell.trim();
             if cell.is_empty() {
@@ -45,14 +52,323 @@ pub async fn index_csv_file(
                 file_search_index.cell_position_field,
                 convert_row_column_to_letter(row_idx + 1, column), // we skip one row
             );
-            doc.add_text(file_search_index.cell_ctx_field, &labels[column]);
+            let header = column_header(&labels, column);
+            doc.add_text(file_search_index.cell_ctx_field, &header);
             doc.add_text(file_search_index.cell_value_field, cell.to_string());
+            let value = CellValue::parse(cell);
+            file_search_index.add_cell_value(&mut doc, &value);
+            insert_cell(&mut row_object, &header, column, cell.into());
+            insert_cell(&mut row_values, &header, column, value.to_json());
         }
+        doc.add_json_object(file_search_index.row_field, row_object);
+        doc.add_json_object(file_search_index.row_values_field, row_values);
 
-        index_writer.add_document(doc)?;
+        docs.push(doc);
     }
-    index_writer.commit()?;
+    file_search_index.set_language(&mut docs, language);
+    let count = file_search_index.replace_documents(&metadata, docs).await?;
     tracing::info!("indexing done.");
 
-    Ok(())
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use tantivy::{collector::TopDocs, query::AllQuery};
+
+    use crate::{
+        index_tantivy::{FacetOptions, QueryType, SearchFilters, SearchOptions},
+        language::DocLanguage,
+        test_utils::test_index,
+    };
+
+    use super::index_csv_file;
+
+    #[tokio::test]
+    async fn test_reindex_csv_replaces_documents() {
+        let file_search_index = test_index();
+        let dir = &file_search_index.dir;
+        let csv_path = dir.join("test.csv");
+        std::fs::write(&csv_path, "Name,Amount\nacme,10\nglobex,20\n").unwrap();
+
+        assert_eq!(
+            2,
+            index_csv_file(file_search_index.clone(), &csv_path, None)
+                .await
+                .unwrap()
+        );
+        std::fs::write(&csv_path, "Name,Amount\nacme,10\n").unwrap();
+        assert_eq!(
+            1,
+            index_csv_file(file_search_index.clone(), &csv_path, None)
+                .await
+                .unwrap()
+        );
+        file_search_index.index_reader.reload().unwrap();
+        let searcher = file_search_index.index_reader.searcher();
+        assert_eq!(1, searcher.num_docs());
+
+        let top_docs = searcher.search(&AllQuery, &TopDocs::with_limit(1)).unwrap();
+        let doc = searcher.doc(top_docs[0].1).unwrap();
+        let file_path = csv_path.canonicalize().unwrap();
+        assert_eq!(
+            Some(file_path.to_str().unwrap()),
+            doc.get_first(file_search_index.file_path_field)
+                .and_then(|v| v.as_text())
+        );
+        assert_eq!(
+            Some("csv"),
+            doc.get_first(file_search_index.file_type_field)
+                .and_then(|v| v.as_text())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_matched_cells() {
+        let file_search_index = test_index();
+        let dir = &file_search_index.dir;
+        let csv_path = dir.join("customers.csv");
+        std::fs::write(
+            &csv_path,
+            "Customer,Contact\nacme,acme corp\nglobex,,globex ltd\n",
+        )
+        .unwrap();
+        index_csv_file(file_search_index.clone(), &csv_path, None)
+            .await
+            .unwrap();
+        file_search_index.index_reader.reload().unwrap();
+
+        let cells = |q, query_type| {
+            let results = file_search_index
+                .search(0, 10, q, &query_type, &SearchOptions::default())
+                .unwrap();
+            results.hits[0]
+                .matched_cells
+                .iter()
+                .map(|c| (c.position.clone(), c.header.clone(), c.value.clone()))
+                .collect::<Vec<_>>()
+        };
+        let cell = |position: &str, header: &str, value: &str| {
+            (position.to_string(), header.to_string(), value.to_string())
+        };
+        assert_eq!(
+            vec![
+                cell("A2", "Customer", "acme"),
+                cell("B2", "Contact", "acme corp")
+            ],
+            cells("acme", QueryType::TermQuery)
+        );
+        assert_eq!(
+            vec![cell("C3", "C", "globex ltd")],
+            cells("ltd", QueryType::FuzzySearch)
+        );
+
+        let results = file_search_index
+            .search(
+                0,
+                10,
+                "globex",
+                &QueryType::TermQuery,
+                &SearchOptions::default(),
+            )
+            .unwrap();
+        let hit = &results.hits[0];
+        assert_eq!(
+            Some(json!({"Customer": "globex", "C": "globex ltd"})),
+            hit.row.clone().map(serde_json::Value::Object)
+        );
+        assert_eq!(Some("Sheet1"), hit.sheet.as_deref());
+        assert_eq!(Some(3), hit.row_number);
+        assert!(!hit.doc.0.contains_key("row"));
+    }
+
+    #[tokio::test]
+    async fn test_search_filters() {
+        let file_search_index = test_index();
+        let dir = &file_search_index.dir;
+        for (file_name, content) in [
+            (
+                "invoices-2024.csv",
+                "Supplier,Customer\nacme,globex\nglobex,acme\n",
+            ),
+            ("other.csv", "Supplier\nacme\n"),
+        ] {
+            std::fs::write(dir.join(file_name), content).unwrap();
+            index_csv_file(file_search_index.clone(), dir.join(file_name), None)
+                .await
+                .unwrap();
+        }
+        file_search_index.index_reader.reload().unwrap();
+
+        let total_hits = |q, query_type, filters: SearchFilters| {
+            file_search_index
+                .search(
+                    0,
+                    10,
+                    q,
+                    &query_type,
+                    &SearchOptions {
+                        filters,
+                        ..Default::default()
+                    },
+                )
+                .unwrap()
+                .total_hits
+        };
+        let header = |header: &str| SearchFilters {
+            header: Some(header.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            3,
+            total_hits("acme", QueryType::TermQuery, SearchFilters::default())
+        );
+        assert_eq!(
+            2,
+            total_hits("acme", QueryType::TermQuery, header("Supplier"))
+        );
+        assert_eq!(
+            2,
+            total_hits("acm", QueryType::FuzzySearch, header("Supplier"))
+        );
+        assert_eq!(
+            1,
+            total_hits("ac.*", QueryType::RegexQuery, header("Customer"))
+        );
+        assert_eq!(
+            2,
+            total_hits("acme", QueryType::QueryParser, header("Customer"))
+        );
+        assert_eq!(
+            1,
+            total_hits(
+                "acme",
+                QueryType::TermQuery,
+                SearchFilters {
+                    file_name: Some("invoices*".to_string()),
+                    ..header("Supplier")
+                }
+            )
+        );
+        assert_eq!(
+            0,
+            total_hits(
+                "acme",
+                QueryType::TermQuery,
+                SearchFilters {
+                    sheet: Some("Sheet2".to_string()),
+                    ..Default::default()
+                }
+            )
+        );
+        assert_eq!(
+            3,
+            total_hits(
+                "acme",
+                QueryType::TermQuery,
+                SearchFilters {
+                    file_type: Some("CSV".to_string()),
+                    ..Default::default()
+                }
+            )
+        );
+        assert_eq!(
+            1,
+            total_hits(
+                "row.Supplier:acme AND file_name:\"other.csv\" AND sheet_name:Sheet1",
+                QueryType::QueryParser,
+                SearchFilters::default()
+            )
+        );
+
+        let options = SearchOptions {
+            facets: FacetOptions {
+                fields: vec!["file_name".into(), "cell_ctx".into(), "file_type".into()],
+                size: 10,
+            },
+            ..Default::default()
+        };
+        let facets = file_search_index
+            .search(0, 1, "acme", &QueryType::TermQuery, &options)
+            .unwrap()
+            .facets;
+        let counts = |field: &str| {
+            facets[field]
+                .iter()
+                .map(|c| (c.value.as_str(), c.count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![("invoices-2024.csv", 2), ("other.csv", 1)],
+            counts("file_name")
+        );
+        assert_eq!(vec![("Supplier", 3), ("Customer", 2)], counts("cell_ctx"));
+        assert_eq!(vec![("csv", 3)], counts("file_type"));
+    }
+
+    #[tokio::test]
+    async fn test_languages() {
+        let file_search_index = test_index();
+        let dir = &file_search_index.dir;
+        for (file_name, content, language) in [
+            (
+                "fr.csv",
+                "Description\nLes sociétés de la région ont signé les contrats avec leurs fournisseurs\nNous avons reçu les factures pour le mois de janvier\n",
+                None,
+            ),
+            (
+                "nl.csv",
+                "Omschrijving\nDe bedrijven in de regio hebben de contracten met hun leveranciers getekend\nWij hebben de facturen voor de maand januari ontvangen\n",
+                None,
+            ),
+            ("short.csv", "Description\nfactures\n", DocLanguage::from_code("fr")),
+        ] {
+            std::fs::write(dir.join(file_name), content).unwrap();
+            index_csv_file(file_search_index.clone(), dir.join(file_name), language)
+                .await
+                .unwrap();
+        }
+        file_search_index.index_reader.reload().unwrap();
+
+        let search = |q, query_type, language: Option<&str>| {
+            let filters = SearchFilters {
+                language: language.and_then(DocLanguage::from_code),
+                ..Default::default()
+            };
+            let options = SearchOptions {
+                filters,
+                ..Default::default()
+            };
+            file_search_index
+                .search(0, 10, q, &query_type, &options)
+                .unwrap()
+        };
+        // stemmed with the analyzer of the language
+        assert_eq!(0, search("société", QueryType::TermQuery, None).total_hits);
+        let hits = search("société", QueryType::TermQuery, Some("fr"));
+        assert_eq!(1, hits.total_hits);
+        assert_eq!(
+            json!(["fr"]),
+            serde_json::to_value(&hits.hits[0].doc.0["language"]).unwrap()
+        );
+        assert_eq!(
+            1,
+            search("leverancier", QueryType::FuzzySearch, Some("nl")).total_hits
+        );
+        assert_eq!(
+            2,
+            search("facture", QueryType::QueryParser, Some("fr")).total_hits
+        );
+        // stop words are not indexed
+        assert_eq!(
+            0,
+            search("les", QueryType::TermQuery, Some("fr")).total_hits
+        );
+        assert_eq!(
+            0,
+            search("factures", QueryType::TermQuery, Some("nl")).total_hits
+        );
+        assert_eq!(3, search("fact.*", QueryType::RegexQuery, None).total_hits);
+    }
 }
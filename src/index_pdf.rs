@@ -3,17 +3,22 @@ use std::{error::Error, path::PathBuf};
 use regex::Regex;
 use tantivy::Document;
 
-use crate::index_tantivy::FileSearchIndex;
+use crate::{
+    index_tantivy::{FileMetadata, FileSearchIndex},
+    language::DocLanguage,
+};
 
 pub async fn index_pdf_file(
     file_search_index: FileSearchIndex,
     path: impl Into<PathBuf>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let path = path.into();
+    language: Option<&'static DocLanguage>,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let path = path.into().canonicalize()?;
+    let metadata = FileMetadata::from_path(&path)?;
     let out = pdf_extract::extract_text(&path)?;
     let regex = Regex::new("(?:\\s*\\.\\s*\\n\\s*)+")?;
 
-    let mut index_writer = file_search_index.index_writer.lock().await;
+    let mut docs = Vec::new();
 
     tracing::info!("indexing start for pdf {path:?}.");
     for (row, line) in regex.split(&out).enumerate() {
@@ -24,20 +29,18 @@ pub async fn index_pdf_file(
         let mut doc = Document::default();
         doc.add_text(
             file_search_index.cell_position_field,
-            &format!("line {}", row + 1),
+            format!("line {}", row + 1),
         );
         doc.add_text(file_search_index.cell_value_field, line);
-        doc.add_text(
-            file_search_index.file_name_field,
-            path.file_name().ok_or("file not found")?.to_string_lossy(),
-        );
+        // the language is detected per passage
+        file_search_index.set_language(std::slice::from_mut(&mut doc), language);
 
-        index_writer.add_document(doc)?;
+        docs.push(doc);
     }
-    index_writer.commit()?;
+    let count = file_search_index.replace_documents(&metadata, docs).await?;
     tracing::info!("indexing done.");
 
-    Ok(())
+    Ok(count)
 }
 
 #[cfg(test)]
@@ -51,9 +54,10 @@ mod test {
         let file_search_index = FileSearchIndex::new(
             &std::env::var("INDEX_DIR_PATH").unwrap_or_else(|_| "/tmp/__tantivy_data".to_string()),
             50_000_000,
+            None,
         )
         .unwrap();
-        index_pdf_file(file_search_index, "test3.pdf")
+        index_pdf_file(file_search_index, "test3.pdf", None)
             .await
             .unwrap();
     }
@@ -1,13 +1,54 @@
-use std::{error::Error, path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    future::Future,
+    io,
+    ops::{Bound, Deref},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 
-use serde::Deserialize;
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
 use tantivy::{
-    collector::TopDocs,
-    query::{FuzzyTermQuery, Query, QueryParser, RegexQuery, TermQuery},
-    schema::{Field, IndexRecordOption, NamedFieldDocument, Schema, STORED, STRING, TEXT},
-    Index, IndexReader, IndexWriter, ReloadPolicy, Term,
+    aggregation::{
+        agg_req::Aggregations,
+        agg_result::{AggregationResult, BucketResult},
+        AggregationCollector,
+    },
+    collector::{Count, TopDocs},
+    f64_to_u64, i64_to_u64,
+    json_utils::JsonTermWriter,
+    query::{
+        BooleanQuery, EnableScoring, FuzzyTermQuery, Query, QueryParser, RangeQuery, RegexQuery,
+        TermQuery,
+    },
+    schema::{
+        Field, IndexRecordOption, JsonObjectOptions, NamedFieldDocument, Schema, TextFieldIndexing,
+        TextOptions, Type, FAST, INDEXED, JSON_END_OF_PATH, STORED, STRING,
+    },
+    time::OffsetDateTime,
+    tokenizer::{
+        AsciiFoldingFilter, Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer,
+        TextAnalyzer,
+    },
+    DateTime, DateTimePrecision, DocAddress, DocSet, Document, Index, IndexReader, IndexWriter,
+    ReloadPolicy, Score, Searcher, Term, TERMINATED,
+};
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard, OwnedRwLockReadGuard, RwLock};
+
+use crate::{
+    group::{GroupCollector, GroupOptions},
+    highlight::{Highlighter, HitSnippet, MatchedCell, TermMatcher},
+    language::{DocLanguage, LANGUAGES, MAX_DETECTION_CHARS},
+    sort::{SortCollector, SortCursor, SortOptions, SortValue},
+    utils::{
+        content_hash, edit_distance, fold, fold_pattern, format_date, glob_to_regex, parse_number,
+        CellValue,
+    },
 };
-use tokio::sync::Mutex;
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -18,10 +59,118 @@ pub enum QueryType {
     QueryParser,
 }
 
+/// Parameters of `QueryType::FuzzySearch`.
+#[derive(Clone, Copy)]
+pub struct FuzzyOptions {
+    /// Levenshtein distance, from 0 to `MAX_FUZZY_DISTANCE`.
+    pub distance: u8,
+    /// Swapping two adjacent characters counts as a single edit.
+    pub transpositions: bool,
+    /// Matches the terms starting with a fuzzy match.
+    pub prefix: bool,
+}
+
+impl Default for FuzzyOptions {
+    fn default() -> Self {
+        FuzzyOptions {
+            distance: 2,
+            transpositions: true,
+            prefix: false,
+        }
+    }
+}
+
+impl FuzzyOptions {
+    /// Tells whether `token` is a fuzzy match of `term`, as the fuzzy term query would.
+    pub fn matches(&self, term: &str, token: &str) -> bool {
+        let distance = self.distance as usize;
+        if !self.prefix {
+            return edit_distance(term, token, self.transpositions) <= distance;
+        }
+        let len = term.chars().count();
+        (len.saturating_sub(distance)..=len + distance).any(|prefix_len| {
+            let prefix = token.chars().take(prefix_len).collect::<String>();
+            prefix.chars().count() == prefix_len
+                && edit_distance(term, &prefix, self.transpositions) <= distance
+        })
+    }
+
+    pub fn query(&self, term: Term) -> Box<dyn Query> {
+        if self.prefix {
+            Box::new(FuzzyTermQuery::new_prefix(
+                term,
+                self.distance,
+                self.transpositions,
+            ))
+        } else {
+            Box::new(FuzzyTermQuery::new(
+                term,
+                self.distance,
+                self.transpositions,
+            ))
+        }
+    }
+}
+
+/// How to run a search besides the query itself.
+#[derive(Default)]
+pub struct SearchOptions {
+    pub filters: SearchFilters,
+    pub fuzzy: FuzzyOptions,
+    /// Adds a highlighted snippet of `cell_value` of at most that many characters to
+    /// every hit.
+    pub snippet_max_chars: Option<usize>,
+    pub facets: FacetOptions,
+    pub group: Option<GroupOptions>,
+    /// Ignored when grouped, groups are sorted by relevance.
+    pub sort: SortOptions,
+    /// The hits start after the cursor instead of the page, not when grouped.
+    pub search_after: Option<SortCursor>,
+}
+
+/// Fields whose values can be counted over the hits of a search.
+pub static FACET_FIELDS: [&str; 4] = [
+    FILE_NAME_FIELD,
+    SHEET_NAME_FIELD,
+    CELL_CTX_FIELD,
+    FILE_TYPE_FIELD,
+];
+
+/// Counts the hits per value of each of the `fields`, among `FACET_FIELDS`.
+#[derive(Default)]
+pub struct FacetOptions {
+    pub fields: Vec<String>,
+    /// Number of values returned per field, the most frequent first.
+    pub size: usize,
+}
+
+#[derive(Serialize)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: u64,
+}
+
+/// Restricts a search, unset filters match every document.
+#[derive(Default)]
+pub struct SearchFilters {
+    /// Glob pattern, e.g. `invoices*`
+    pub file_name: Option<String>,
+    pub sheet: Option<String>,
+    /// Column header, the query only matches the cells of that column.
+    pub header: Option<String>,
+    pub file_type: Option<String>,
+    /// The query is also analyzed with the stemmer and stop words of the language.
+    pub language: Option<&'static DocLanguage>,
+}
+
 #[derive(Clone)]
 pub struct FileSearchIndex {
+    pub index_dir: PathBuf,
+    pub writer_memory_arena: usize,
+    pub stemmer: Option<Language>,
     pub index: Index,
-    pub index_writer: Arc<Mutex<IndexWriter>>,
+    /// Taken once the index is closed, e.g. when a rebuild replaced it.
+    pub index_writer: Arc<Mutex<Option<IndexWriter>>>,
     pub index_reader: IndexReader,
     pub schema: Schema,
     pub cell_position_field: Field,
@@ -29,6 +178,155 @@ pub struct FileSearchIndex {
     pub cell_ctx_field: Field,
     pub file_name_field: Field,
     pub sheet_name_field: Field,
+    pub file_path_field: Field,
+    pub parent_dir_field: Field,
+    pub file_type_field: Field,
+    pub file_size_field: Field,
+    pub modified_at_field: Field,
+    pub content_hash_field: Field,
+    pub indexed_at_field: Field,
+    pub row_field: Field,
+    /// The row with typed numbers and dates, `row` keeps the text of the cells.
+    pub row_values_field: Field,
+    pub row_number_field: Field,
+    pub cell_number_field: Field,
+    pub cell_date_field: Field,
+    pub language_field: Field,
+    /// Cell values analyzed in each language, by language code.
+    pub cell_value_language_fields: BTreeMap<&'static str, Field>,
+}
+
+/// Source file information stored alongside every document indexed from that file.
+pub struct FileMetadata {
+    pub file_path: String,
+    pub file_name: String,
+    pub parent_dir: String,
+    pub file_type: String,
+    pub size: u64,
+    pub modified_at: DateTime,
+    pub content_hash: String,
+}
+
+#[derive(Serialize)]
+pub struct SearchResults {
+    pub total_hits: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_groups: Option<usize>,
+    pub page: usize,
+    pub per_page: usize,
+    pub took_ms: u64,
+    /// Empty when the hits are grouped.
+    pub hits: Vec<SearchHit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<SearchGroup>>,
+    /// `search_after` cursor of the next page, when the page is full.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// Hit counts per value, by field.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub facets: BTreeMap<String, Vec<FacetCount>>,
+}
+
+/// Hits of a file, or of a sheet of a file.
+#[derive(Serialize)]
+pub struct SearchGroup {
+    pub file_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sheet: Option<String>,
+    pub total_hits: u64,
+    /// Best hits first.
+    pub hits: Vec<SearchHit>,
+}
+
+/// Address of a document in the searcher that returned it, only stable until the next commit.
+#[derive(Serialize)]
+pub struct HitAddress {
+    pub segment_ord: u32,
+    pub doc_id: u32,
+}
+
+impl From<DocAddress> for HitAddress {
+    fn from(doc_address: DocAddress) -> Self {
+        HitAddress {
+            segment_ord: doc_address.segment_ord,
+            doc_id: doc_address.doc_id,
+        }
+    }
+}
+
+/// A spreadsheet row and the stored fields of a document are returned side by side.
+#[derive(Serialize)]
+pub struct SearchHit {
+    pub score: Score,
+    pub doc_address: HitAddress,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row: Option<Map<String, serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sheet: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row_number: Option<u64>,
+    #[serde(flatten)]
+    pub doc: NamedFieldDocument,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub matched_cells: Vec<MatchedCell>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<HitSnippet>,
+}
+
+#[derive(Serialize)]
+pub struct IndexedFile {
+    pub file_path: String,
+    pub file_name: String,
+    pub file_type: String,
+    pub size: u64,
+    pub modified_at: String,
+    pub indexed_at: String,
+    pub document_count: u64,
+    pub sheets: Vec<String>,
+}
+
+/// Completion of a prefix typed in the search box.
+#[derive(Serialize)]
+pub struct Suggestion {
+    pub text: String,
+    /// `cell_value`, `cell_ctx` (header) or `file_name`.
+    pub field: String,
+    /// Number of documents holding the term, deleted ones included until their segment
+    /// is merged.
+    pub doc_freq: u32,
+}
+
+pub struct SuggestOptions {
+    pub limit: usize,
+    /// Also completes column headers.
+    pub headers: bool,
+    pub file_names: bool,
+}
+
+impl FileMetadata {
+    /// `path` is expected to be canonical, as its string form is the file identity in the index.
+    pub fn from_path(path: &Path) -> Result<FileMetadata, Box<dyn Error + Send + Sync>> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(FileMetadata {
+            file_path: path.to_string_lossy().to_string(),
+            file_name: path
+                .file_name()
+                .ok_or("file not found")?
+                .to_string_lossy()
+                .to_string(),
+            parent_dir: path
+                .parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            file_type: path
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
+            size: metadata.len(),
+            modified_at: DateTime::from_utc(OffsetDateTime::from(metadata.modified()?)),
+            content_hash: content_hash(path)?,
+        })
+    }
 }
 
 pub static CELL_POSITION_FIELD: &str = "cell_position";
@@ -36,48 +334,618 @@ pub static CELL_CTX_FIELD: &str = "cell_ctx";
 pub static CELL_VALUE_FIELD: &str = "cell_value";
 pub static FILE_NAME_FIELD: &str = "file_name";
 pub static SHEET_NAME_FIELD: &str = "sheet_name";
+pub static FILE_PATH_FIELD: &str = "file_path";
+pub static PARENT_DIR_FIELD: &str = "parent_dir";
+pub static FILE_TYPE_FIELD: &str = "file_type";
+pub static FILE_SIZE_FIELD: &str = "file_size";
+pub static MODIFIED_AT_FIELD: &str = "modified_at";
+pub static CONTENT_HASH_FIELD: &str = "content_hash";
+pub static INDEXED_AT_FIELD: &str = "indexed_at";
+pub static ROW_FIELD: &str = "row";
+pub static ROW_VALUES_FIELD: &str = "row_values";
+pub static ROW_NUMBER_FIELD: &str = "row_number";
+pub static CELL_NUMBER_FIELD: &str = "cell_number";
+pub static CELL_DATE_FIELD: &str = "cell_date";
+pub static LANGUAGE_FIELD: &str = "language";
+
+pub static FOLDED_TOKENIZER: &str = "folded";
+
+pub const MAX_TERM_EXPANSIONS: usize = 1000;
+pub const MAX_FUZZY_DISTANCE: u8 = 2;
+/// Terms scanned per segment and field to find the most frequent completions.
+pub const MAX_SUGGEST_SCAN: usize = 10_000;
 
 impl FileSearchIndex {
-    pub fn new(path: &str, writer_memory_arena: usize) -> Result<FileSearchIndex, Box<dyn Error>> {
+    /// Opens the index at `path`, created when missing. With a `stemmer`, the words of
+    /// the cell values are also reduced to their stem in that language.
+    pub fn new(
+        path: &str,
+        writer_memory_arena: usize,
+        stemmer: Option<Language>,
+    ) -> Result<FileSearchIndex, Box<dyn Error + Send + Sync>> {
         let index_dir = PathBuf::from(path);
         if !index_dir.exists() {
             std::fs::create_dir(index_dir.as_path())?;
         }
-        let mut schema_builder = Schema::builder();
-        let cell_position_field =
-            schema_builder.add_text_field(CELL_POSITION_FIELD, STRING | STORED);
-        let cell_value_field = schema_builder.add_text_field(CELL_VALUE_FIELD, TEXT | STORED);
-        let cell_ctx_field = schema_builder.add_text_field(CELL_CTX_FIELD, STRING | STORED);
-        let file_name_field = schema_builder.add_text_field(FILE_NAME_FIELD, STRING | STORED);
-        let sheet_name_field = schema_builder.add_text_field(SHEET_NAME_FIELD, STRING | STORED);
-        let schema = schema_builder.build();
+        let schema = FileSearchIndex::schema(stemmer);
         let index = Index::open_or_create(
             tantivy::directory::MmapDirectory::open(&index_dir)?,
             schema.clone(),
         )?;
-        let index_writer = Arc::new(Mutex::new(index.writer(writer_memory_arena)?)); // 50mb
+        index
+            .tokenizers()
+            .register(&folded_tokenizer_name(stemmer), folded_tokenizer(stemmer));
+        for language in &LANGUAGES {
+            index
+                .tokenizers()
+                .register(&language.tokenizer_name(), language.tokenizer());
+        }
+        let index_writer = Arc::new(Mutex::new(Some(index.writer(writer_memory_arena)?))); // 50mb
         let index_reader = index
             .reader_builder()
             .reload_policy(ReloadPolicy::OnCommit)
             .try_into()?;
+        let field = |name: &str| schema.get_field(name);
         Ok(FileSearchIndex {
-            schema,
+            index_dir,
+            writer_memory_arena,
+            stemmer,
             index,
             index_writer,
             index_reader,
-            cell_value_field,
-            cell_ctx_field,
-            file_name_field,
-            sheet_name_field,
-            cell_position_field,
+            cell_value_field: field(CELL_VALUE_FIELD)?,
+            cell_ctx_field: field(CELL_CTX_FIELD)?,
+            file_name_field: field(FILE_NAME_FIELD)?,
+            sheet_name_field: field(SHEET_NAME_FIELD)?,
+            cell_position_field: field(CELL_POSITION_FIELD)?,
+            file_path_field: field(FILE_PATH_FIELD)?,
+            parent_dir_field: field(PARENT_DIR_FIELD)?,
+            file_type_field: field(FILE_TYPE_FIELD)?,
+            file_size_field: field(FILE_SIZE_FIELD)?,
+            modified_at_field: field(MODIFIED_AT_FIELD)?,
+            content_hash_field: field(CONTENT_HASH_FIELD)?,
+            indexed_at_field: field(INDEXED_AT_FIELD)?,
+            row_field: field(ROW_FIELD)?,
+            row_values_field: field(ROW_VALUES_FIELD)?,
+            row_number_field: field(ROW_NUMBER_FIELD)?,
+            cell_number_field: field(CELL_NUMBER_FIELD)?,
+            cell_date_field: field(CELL_DATE_FIELD)?,
+            language_field: field(LANGUAGE_FIELD)?,
+            cell_value_language_fields: LANGUAGES
+                .iter()
+                .map(|language| Ok((language.code, field(&language.field_name())?)))
+                .collect::<tantivy::Result<_>>()?,
+            schema,
+        })
+    }
+
+    /// Cell values and rows are analyzed with the folded tokenizer, so that `societe`
+    /// matches `Société`.
+    pub fn schema(stemmer: Option<Language>) -> Schema {
+        let folded = TextFieldIndexing::default()
+            .set_tokenizer(&folded_tokenizer_name(stemmer))
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field(CELL_POSITION_FIELD, STRING | STORED);
+        schema_builder.add_text_field(
+            CELL_VALUE_FIELD,
+            TextOptions::default()
+                .set_indexing_options(folded.clone())
+                .set_stored(),
+        );
+        schema_builder.add_text_field(CELL_CTX_FIELD, STRING | STORED | FAST);
+        schema_builder.add_text_field(FILE_NAME_FIELD, STRING | STORED | FAST);
+        schema_builder.add_text_field(SHEET_NAME_FIELD, STRING | STORED | FAST);
+        schema_builder.add_text_field(FILE_PATH_FIELD, STRING | STORED | FAST);
+        schema_builder.add_text_field(PARENT_DIR_FIELD, STRING | STORED);
+        schema_builder.add_text_field(FILE_TYPE_FIELD, STRING | STORED | FAST);
+        schema_builder.add_u64_field(FILE_SIZE_FIELD, INDEXED | STORED | FAST);
+        schema_builder.add_date_field(MODIFIED_AT_FIELD, INDEXED | STORED | FAST);
+        schema_builder.add_text_field(CONTENT_HASH_FIELD, STRING | STORED);
+        schema_builder.add_date_field(INDEXED_AT_FIELD, INDEXED | STORED | FAST);
+        schema_builder.add_json_field(
+            ROW_FIELD,
+            JsonObjectOptions::default()
+                .set_indexing_options(folded)
+                .set_stored(),
+        );
+        schema_builder.add_json_field(
+            ROW_VALUES_FIELD,
+            JsonObjectOptions::default()
+                .set_indexing_options(TextFieldIndexing::default().set_tokenizer("raw"))
+                .set_stored()
+                // the columns can be sorted by
+                .set_fast(None),
+        );
+        schema_builder.add_u64_field(ROW_NUMBER_FIELD, INDEXED | STORED | FAST);
+        schema_builder.add_f64_field(CELL_NUMBER_FIELD, INDEXED | FAST);
+        schema_builder.add_date_field(CELL_DATE_FIELD, INDEXED | FAST);
+        schema_builder.add_text_field(LANGUAGE_FIELD, STRING | STORED | FAST);
+        for language in &LANGUAGES {
+            let indexing = TextFieldIndexing::default()
+                .set_tokenizer(&language.tokenizer_name())
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+            schema_builder.add_text_field(
+                &language.field_name(),
+                TextOptions::default().set_indexing_options(indexing),
+            );
+        }
+        schema_builder.build()
+    }
+
+    /// Moves the index at `path` aside when it was created with another schema, e.g.
+    /// before a change of analyzer, and returns the files it indexed so that they can be
+    /// indexed again. See `remove_outdated`. Fails, leaving the index untouched, when its
+    /// files can't be listed.
+    pub fn migrate(
+        path: &str,
+        stemmer: Option<Language>,
+    ) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+        let index_dir = PathBuf::from(path);
+        if !index_dir.exists()
+            || !Index::exists(&tantivy::directory::MmapDirectory::open(&index_dir)?)?
+        {
+            return Ok(vec![]);
+        }
+        let index = Index::open_in_dir(&index_dir)?;
+        let schema = index.schema();
+        if schema == FileSearchIndex::schema(stemmer) {
+            return Ok(vec![]);
+        }
+
+        // the stored file names are not enough to find the files again
+        let file_path_field = schema.get_field(FILE_PATH_FIELD).map_err(|_| {
+            format!(
+                "index {index_dir:?} has another schema and no file paths, its files can't be \
+                 indexed again: remove it and index them again"
+            )
+        })?;
+        let searcher = index.reader()?.searcher();
+        let mut indexed = BTreeSet::new();
+        for segment_reader in searcher.segment_readers() {
+            let inverted_index = segment_reader.inverted_index(file_path_field)?;
+            let mut stream = inverted_index.terms().stream()?;
+            while stream.advance() {
+                indexed.insert(String::from_utf8_lossy(stream.key()).to_string());
+            }
+        }
+        // the terms of deleted documents stay in the dictionary until a merge
+        let mut file_paths = vec![];
+        for file_path in indexed {
+            let query = TermQuery::new(
+                Term::from_field_text(file_path_field, &file_path),
+                IndexRecordOption::Basic,
+            );
+            if searcher.search(&query, &Count)? > 0 {
+                file_paths.push(PathBuf::from(file_path));
+            }
+        }
+
+        let outdated_dir = sibling_dir(&index_dir, "outdated")?;
+        if outdated_dir.exists() {
+            std::fs::remove_dir_all(&outdated_dir)?;
+        }
+        std::fs::rename(&index_dir, &outdated_dir)?;
+        tracing::warn!(
+            "index schema changed, {index_dir:?} moved to {outdated_dir:?}, {} files must be indexed again",
+            file_paths.len()
+        );
+        Ok(file_paths)
+    }
+
+    /// Removes the index moved aside by `migrate`, once this one could be opened.
+    pub fn remove_outdated(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let outdated_dir = sibling_dir(&self.index_dir, "outdated")?;
+        if outdated_dir.exists() {
+            std::fs::remove_dir_all(&outdated_dir)?;
+            tracing::warn!("outdated index {outdated_dir:?} removed");
+        }
+        Ok(())
+    }
+
+    /// Opens an empty index in a directory next to this one, used to rebuild the index
+    /// while this one keeps being served.
+    pub fn shadow(&self) -> Result<FileSearchIndex, Box<dyn Error + Send + Sync>> {
+        let shadow_dir = sibling_dir(&self.index_dir, "shadow")?;
+        if shadow_dir.exists() {
+            tracing::warn!("removing leftover shadow index {shadow_dir:?}");
+            std::fs::remove_dir_all(&shadow_dir)?;
+        }
+        FileSearchIndex::new(
+            &shadow_dir.to_string_lossy(),
+            self.writer_memory_arena,
+            self.stemmer,
+        )
+    }
+
+    /// Waits for pending merges and releases the writer, the index can still be read but
+    /// no longer written, by this instance or its clones.
+    pub async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(index_writer) = self.index_writer.lock().await.take() {
+            index_writer.wait_merging_threads()?;
+        }
+        Ok(())
+    }
+
+    async fn writer(
+        &self,
+    ) -> Result<MappedMutexGuard<'_, IndexWriter>, Box<dyn Error + Send + Sync>> {
+        MutexGuard::try_map(self.index_writer.lock().await, Option::as_mut)
+            .map_err(|_| format!("index {:?} is closed", self.index_dir).into())
+    }
+
+    /// Matches the documents of the file at `file_path`.
+    pub fn file_path_query(&self, file_path: &str) -> Box<dyn Query> {
+        Box::new(TermQuery::new(
+            Term::from_field_text(self.file_path_field, file_path),
+            IndexRecordOption::Basic,
+        ))
+    }
+
+    /// Matches the documents of every file under `directory`.
+    pub fn directory_query(&self, directory: &str) -> tantivy::Result<Box<dyn Query>> {
+        Ok(Box::new(RegexQuery::from_pattern(
+            &format!("{}/.*", regex::escape(directory.trim_end_matches('/'))),
+            self.file_path_field,
+        )?))
+    }
+
+    /// Matches the documents of the files whose name matches the glob `pattern`.
+    pub fn file_name_query(&self, pattern: &str) -> tantivy::Result<Box<dyn Query>> {
+        Ok(Box::new(RegexQuery::from_pattern(
+            &glob_to_regex(pattern),
+            self.file_name_field,
+        )?))
+    }
+
+    /// Deletes the documents matching `query` and returns how many were deleted.
+    pub async fn delete_documents(
+        &self,
+        query: Box<dyn Query>,
+    ) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let mut index_writer = self.writer().await?;
+        // no other write can happen while we hold the writer, so the count is accurate
+        self.index_reader.reload()?;
+        let count = self.index_reader.searcher().search(&query, &Count)?;
+        if count > 0 {
+            index_writer.delete_query(query)?;
+            index_writer.commit()?;
+        }
+        Ok(count as u64)
+    }
+
+    /// Deletes the documents of the file at `file_path`, or of every file under it when it
+    /// was a directory.
+    pub async fn delete_path(&self, file_path: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let query = BooleanQuery::union(vec![
+            self.file_path_query(file_path),
+            self.directory_query(file_path)?,
+        ]);
+        self.delete_documents(Box::new(query)).await
+    }
+
+    /// Deletes the documents of the given files within a single commit.
+    pub async fn delete_files(
+        &self,
+        file_paths: &[String],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut index_writer = self.writer().await?;
+        for file_path in file_paths {
+            index_writer.delete_term(Term::from_field_text(self.file_path_field, file_path));
+        }
+        index_writer.commit()?;
+        Ok(())
+    }
+
+    /// Returns every indexed file with its number of documents, sorted by path.
+    pub fn file_doc_counts(&self) -> tantivy::Result<BTreeMap<String, u64>> {
+        let searcher = self.index_reader.searcher();
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        for segment_reader in searcher.segment_readers() {
+            let inverted_index = segment_reader.inverted_index(self.file_path_field)?;
+            let alive_bitset = segment_reader.alive_bitset();
+            let mut terms = inverted_index.terms().stream()?;
+            while terms.advance() {
+                let term_info = terms.value();
+                let count = match alive_bitset {
+                    None => term_info.doc_freq as u64,
+                    Some(alive_bitset) => {
+                        let mut postings = inverted_index
+                            .read_postings_from_terminfo(term_info, IndexRecordOption::Basic)?;
+                        let mut count = 0;
+                        while postings.doc() != TERMINATED {
+                            if alive_bitset.is_alive(postings.doc()) {
+                                count += 1;
+                            }
+                            postings.advance();
+                        }
+                        count
+                    }
+                };
+                if count > 0 {
+                    *counts
+                        .entry(String::from_utf8_lossy(terms.key()).to_string())
+                        .or_default() += count;
+                }
+            }
+        }
+        Ok(counts)
+    }
+
+    fn file_document(&self, file_path: &str) -> tantivy::Result<Option<Document>> {
+        let searcher = self.index_reader.searcher();
+        let query = self.file_path_query(file_path);
+        let Some((_, doc_address)) = searcher.search(&query, &TopDocs::with_limit(1))?.pop() else {
+            return Ok(None);
+        };
+        Ok(Some(searcher.doc(doc_address)?))
+    }
+
+    /// Returns the metadata stored for an indexed file.
+    pub fn file_metadata(&self, file_path: &str) -> tantivy::Result<Option<FileMetadata>> {
+        Ok(self
+            .file_document(file_path)?
+            .map(|doc| self.metadata_from_document(&doc)))
+    }
+
+    fn metadata_from_document(&self, doc: &Document) -> FileMetadata {
+        let text = |field| {
+            doc.get_first(field)
+                .and_then(|v| v.as_text())
+                .unwrap_or_default()
+                .to_string()
+        };
+        FileMetadata {
+            file_path: text(self.file_path_field),
+            file_name: text(self.file_name_field),
+            parent_dir: text(self.parent_dir_field),
+            file_type: text(self.file_type_field),
+            size: doc
+                .get_first(self.file_size_field)
+                .and_then(|v| v.as_u64())
+                .unwrap_or_default(),
+            modified_at: date_or_epoch(doc, self.modified_at_field),
+            content_hash: text(self.content_hash_field),
+        }
+    }
+
+    /// Lists a page of the indexed files, sorted by path, along with the number of files.
+    pub fn indexed_files(
+        &self,
+        page: usize,
+        per_page: usize,
+    ) -> Result<(usize, Vec<IndexedFile>), Box<dyn Error + Send + Sync>> {
+        let searcher = self.index_reader.searcher();
+        let doc_counts = self.file_doc_counts()?;
+        let total = doc_counts.len();
+        // a page beyond the last file is empty
+        let skip = page.checked_mul(per_page).unwrap_or(total);
+        let mut files = Vec::with_capacity(per_page.min(total.saturating_sub(skip)));
+        for (file_path, document_count) in doc_counts.into_iter().skip(skip).take(per_page) {
+            let Some(doc) = self.file_document(&file_path)? else {
+                continue;
+            };
+            let metadata = self.metadata_from_document(&doc);
+
+            let sheets_agg: Aggregations = serde_json::from_value(json!({
+                "sheets": {
+                    "terms": { "field": SHEET_NAME_FIELD, "size": 1000, "order": { "_key": "asc" } }
+                }
+            }))?;
+            let collector = AggregationCollector::from_aggs(sheets_agg, Default::default());
+            let sheets = match searcher
+                .search(&self.file_path_query(&file_path), &collector)?
+                .0
+                .remove("sheets")
+            {
+                Some(AggregationResult::BucketResult(BucketResult::Terms { buckets, .. })) => {
+                    buckets.into_iter().map(|b| b.key.to_string()).collect()
+                }
+                _ => vec![],
+            };
+
+            files.push(IndexedFile {
+                file_path: metadata.file_path,
+                file_name: metadata.file_name,
+                file_type: metadata.file_type,
+                size: metadata.size,
+                modified_at: format_date(metadata.modified_at),
+                indexed_at: format_date(date_or_epoch(&doc, self.indexed_at_field)),
+                document_count,
+                sheets,
+            });
+        }
+        Ok((total, files))
+    }
+
+    /// Replaces every document indexed for the file described by `metadata` by `docs`,
+    /// within a single commit. The file metadata is added to each document. On failure,
+    /// pending operations are rolled back so the previous content stays searchable.
+    pub async fn replace_documents(
+        &self,
+        metadata: &FileMetadata,
+        docs: Vec<Document>,
+    ) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let file_path = &metadata.file_path;
+        let indexed_at = DateTime::from_utc(OffsetDateTime::now_utc());
+        let mut index_writer = self.writer().await?;
+        let replace = || -> tantivy::Result<u64> {
+            index_writer.delete_term(Term::from_field_text(self.file_path_field, file_path));
+            let mut count = 0;
+            for mut doc in docs {
+                doc.add_text(self.file_path_field, file_path);
+                doc.add_text(self.file_name_field, &metadata.file_name);
+                doc.add_text(self.parent_dir_field, &metadata.parent_dir);
+                doc.add_text(self.file_type_field, &metadata.file_type);
+                doc.add_u64(self.file_size_field, metadata.size);
+                doc.add_date(self.modified_at_field, metadata.modified_at);
+                doc.add_text(self.content_hash_field, &metadata.content_hash);
+                doc.add_date(self.indexed_at_field, indexed_at);
+                index_writer.add_document(doc)?;
+                count += 1;
+            }
+            index_writer.commit()?;
+            Ok(count)
+        };
+        match replace() {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                tracing::error!("could not replace documents for {file_path}: {e:?}");
+                index_writer.rollback()?;
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Replaces the metadata of the documents of an indexed file, e.g. its modification
+    /// time once touched, without reading the file again.
+    pub async fn update_metadata(
+        &self,
+        metadata: &FileMetadata,
+    ) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let docs = self.file_documents(&metadata.file_path)?;
+        self.replace_documents(metadata, docs).await
+    }
+
+    /// Documents of the file at `file_path` without their metadata, as they were given to
+    /// `replace_documents`. Their values that are indexed but not stored are restored from
+    /// the fast fields and the language of the documents.
+    fn file_documents(&self, file_path: &str) -> tantivy::Result<Vec<Document>> {
+        let metadata_fields = [
+            self.file_path_field,
+            self.file_name_field,
+            self.parent_dir_field,
+            self.file_type_field,
+            self.file_size_field,
+            self.modified_at_field,
+            self.content_hash_field,
+            self.indexed_at_field,
+        ];
+        let searcher = self.index_reader.searcher();
+        let weight = self
+            .file_path_query(file_path)
+            .weight(EnableScoring::disabled_from_searcher(&searcher))?;
+        let mut docs = vec![];
+        for segment_reader in searcher.segment_readers() {
+            let store_reader = segment_reader.get_store_reader(1)?;
+            let alive_bitset = segment_reader.alive_bitset();
+            let numbers = segment_reader.fast_fields().f64(CELL_NUMBER_FIELD)?;
+            let dates = segment_reader.fast_fields().date(CELL_DATE_FIELD)?;
+            let mut scorer = weight.scorer(segment_reader, 1.0)?;
+            while scorer.doc() != TERMINATED {
+                let doc_id = scorer.doc();
+                scorer.advance();
+                if alive_bitset.is_some_and(|alive_bitset| !alive_bitset.is_alive(doc_id)) {
+                    continue;
+                }
+                let stored = store_reader.get(doc_id)?;
+                let mut doc = Document::default();
+                for field_value in stored.field_values() {
+                    if !metadata_fields.contains(&field_value.field()) {
+                        doc.add_field_value(field_value.field(), field_value.value().clone());
+                    }
+                }
+                for number in numbers.values_for_doc(doc_id) {
+                    doc.add_f64(self.cell_number_field, number);
+                }
+                for date in dates.values_for_doc(doc_id) {
+                    doc.add_date(self.cell_date_field, date);
+                }
+                let language_field = stored
+                    .get_first(self.language_field)
+                    .and_then(|code| code.as_text())
+                    .and_then(|code| self.cell_value_language_fields.get(code));
+                if let Some(field) = language_field {
+                    for value in stored.get_all(self.cell_value_field) {
+                        doc.add_field_value(*field, value.clone());
+                    }
+                }
+                docs.push(doc);
+            }
+        }
+        Ok(docs)
+    }
+
+    /// Tokens of a text, as indexed in `field`, e.g. `cell_value`.
+    pub fn tokenize(&self, field: Field, text: &str) -> tantivy::Result<Vec<String>> {
+        let mut tokenizer = self.index.tokenizer_for_field(field)?;
+        let mut token_stream = tokenizer.token_stream(text);
+        let mut tokens = vec![];
+        while let Some(token) = token_stream.next() {
+            tokens.push(token.text.clone());
+        }
+        Ok(tokens)
+    }
+
+    /// Normalizes a term the way cell values are indexed in `field`. Text made of several
+    /// tokens, e.g. the number `1.5`, is only lowercased and folded.
+    pub fn normalize(&self, field: Field, text: &str) -> tantivy::Result<String> {
+        let mut tokens = self.tokenize(field, text)?;
+        Ok(match tokens.len() {
+            1 => tokens.remove(0),
+            _ => fold(text),
         })
     }
+
+    /// Field holding the cell values analyzed in `language`.
+    pub fn language_value_field(&self, language: &DocLanguage) -> Field {
+        self.cell_value_language_fields[language.code]
+    }
+
+    /// Tells whether `field` holds the cell values, analyzed in a language or not.
+    pub fn is_cell_value_field(&self, field: Field) -> bool {
+        field == self.cell_value_field
+            || self
+                .cell_value_language_fields
+                .values()
+                .any(|f| *f == field)
+    }
+
+    /// Tags `docs` with `language`, or the language detected from their cell values, and
+    /// indexes their cell values with the analyzer of that language. Nothing is done when
+    /// the language can't be detected.
+    pub fn set_language(&self, docs: &mut [Document], language: Option<&'static DocLanguage>) {
+        let language = language.or_else(|| {
+            let mut text = String::new();
+            let values = docs
+                .iter()
+                .flat_map(|doc| doc.get_all(self.cell_value_field))
+                .flat_map(|value| value.as_text());
+            for value in values {
+                if text.len() >= MAX_DETECTION_CHARS {
+                    break;
+                }
+                text += value;
+                text.push(' ');
+            }
+            DocLanguage::detect(&text)
+        });
+        let Some(language) = language else {
+            return;
+        };
+        let field = self.language_value_field(language);
+        for doc in docs {
+            let values: Vec<String> = doc
+                .get_all(self.cell_value_field)
+                .flat_map(|value| value.as_text())
+                .map(String::from)
+                .collect();
+            for value in values {
+                doc.add_text(field, value);
+            }
+            doc.add_text(self.language_field, language.code);
+        }
+    }
+
+    /// Searches `field`, `cell_value` or one of its language fields.
     pub fn convert_query_type_to_query(
         &self,
+        field: Field,
         q: &str,
         query_type: &QueryType,
+        fuzzy: &FuzzyOptions,
     ) -> Result<Box<dyn Query>, Box<dyn Error>> {
-        let search_field = self.cell_value_field;
+        let search_field = field;
         let query: Box<dyn Query> = match query_type {
             QueryType::TermQuery => Box::new(TermQuery::new(
                 Term::from_field_text(search_field, q),
@@ -88,39 +956,1370 @@ impl FileSearchIndex {
                 q,
                 search_field,
             )?),
-            QueryType::FuzzySearch => Box::new(FuzzyTermQuery::new(
-                Term::from_field_text(search_field, q),
-                2, // todo lehvenstein distance should be a param
-                true,
+            // every word must match
+            QueryType::FuzzySearch => Box::new(BooleanQuery::intersection(
+                self.tokenize(search_field, q)?
+                    .iter()
+                    .map(|token| fuzzy.query(Term::from_field_text(search_field, token)))
+                    .collect(),
             )),
             QueryType::QueryParser => {
-                let query_parser = QueryParser::for_index(&self.index, vec![self.cell_value_field]);
+                let query_parser = QueryParser::for_index(&self.index, vec![search_field]);
                 let query = query_parser.parse_query(q)?;
                 Box::new(query)
             }
         };
         Ok(query)
     }
+
+    /// Indexes a number or a date cell of a row in the typed cell fields.
+    pub fn add_cell_value(&self, doc: &mut Document, value: &CellValue) {
+        match value {
+            CellValue::Number(number) => doc.add_f64(self.cell_number_field, *number),
+            CellValue::Date(date) => doc.add_date(self.cell_date_field, *date),
+            CellValue::Text(_) => {}
+        }
+    }
+
+    /// Term of the text `text` in the cells of the column `header`.
+    pub fn column_term(&self, header: &str, text: &str) -> Term {
+        let mut term = Term::with_capacity(64);
+        let mut json_term_writer = JsonTermWriter::from_field_and_json_path(
+            self.row_field,
+            &column_json_path(header),
+            false,
+            &mut term,
+        );
+        json_term_writer.set_str(text);
+        term
+    }
+
+    /// Term of a number or a date of the column `header`, `value` is the value mapped to
+    /// u64 like tantivy does for fast fields.
+    fn column_fast_value_term(&self, header: &str, typ: Type, value: u64) -> Term {
+        let mut term = self.column_type_term(header, typ);
+        term.append_bytes(&value.to_be_bytes());
+        term
+    }
+
+    /// Term without value, the prefix of the terms of type `typ` of the column `header`.
+    fn column_type_term(&self, header: &str, typ: Type) -> Term {
+        let mut term = Term::with_capacity(64);
+        JsonTermWriter::from_field_and_json_path(
+            self.row_values_field,
+            &column_json_path(header),
+            false,
+            &mut term,
+        )
+        .close_path_and_set_type(typ);
+        term
+    }
+
+    /// Whether some cells of the column `header` hold values of type `typ`.
+    pub fn column_has_type(&self, header: &str, typ: Type) -> tantivy::Result<bool> {
+        let prefix = self.column_type_term(header, typ);
+        let prefix = prefix.serialized_value_bytes();
+        for segment_reader in self.index_reader.searcher().segment_readers() {
+            let inverted_index = segment_reader.inverted_index(self.row_values_field)?;
+            let mut terms = inverted_index.terms().range().ge(prefix).into_stream()?;
+            if terms.advance() && terms.key().starts_with(prefix) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Term of the number `number` in the cells of the column `header`.
+    pub fn column_number_term(&self, header: &str, number: f64) -> Term {
+        self.column_fast_value_term(header, Type::F64, f64_to_u64(number))
+    }
+
+    /// Matches the numbers of the column `header` within the bounds.
+    pub fn column_number_range(
+        &self,
+        header: &str,
+        lower: Bound<f64>,
+        upper: Bound<f64>,
+    ) -> RangeQuery {
+        self.column_range(
+            header,
+            Type::F64,
+            lower.map(f64_to_u64),
+            upper.map(f64_to_u64),
+        )
+    }
+
+    /// Matches the dates of the column `header` within the bounds.
+    pub fn column_date_range(
+        &self,
+        header: &str,
+        lower: Bound<DateTime>,
+        upper: Bound<DateTime>,
+    ) -> RangeQuery {
+        // dates of json fields are indexed with a precision of a second
+        let to_u64 = |date: DateTime| {
+            i64_to_u64(
+                date.truncate(DateTimePrecision::Seconds)
+                    .into_timestamp_nanos(),
+            )
+        };
+        self.column_range(header, Type::Date, lower.map(to_u64), upper.map(to_u64))
+    }
+
+    fn column_range(
+        &self,
+        header: &str,
+        typ: Type,
+        lower: Bound<u64>,
+        upper: Bound<u64>,
+    ) -> RangeQuery {
+        // the terms of the other columns and types must stay out of unbounded ranges
+        let lower = match lower {
+            Bound::Unbounded => Bound::Included(u64::MIN),
+            bound => bound,
+        };
+        let upper = match upper {
+            Bound::Unbounded => Bound::Included(u64::MAX),
+            bound => bound,
+        };
+        let term = |value| self.column_fast_value_term(header, typ, value);
+        RangeQuery::new_term_bounds(
+            ROW_VALUES_FIELD.to_string(),
+            Type::Json,
+            &lower.map(term),
+            &upper.map(term),
+        )
+    }
+
+    /// Terms of the column `header` whose text matches, up to `MAX_TERM_EXPANSIONS`.
+    pub fn expand_column_terms(
+        &self,
+        header: &str,
+        matches: impl Fn(&str) -> bool,
+    ) -> tantivy::Result<Vec<Term>> {
+        let prefix = self.column_term(header, "");
+        let prefix = prefix.serialized_value_bytes();
+        let mut texts = BTreeSet::new();
+        for segment_reader in self.index_reader.searcher().segment_readers() {
+            let inverted_index = segment_reader.inverted_index(self.row_field)?;
+            let mut terms = inverted_index.terms().range().ge(prefix).into_stream()?;
+            while texts.len() < MAX_TERM_EXPANSIONS && terms.advance() {
+                let Some(text) = terms.key().strip_prefix(prefix) else {
+                    break;
+                };
+                let text = String::from_utf8_lossy(text);
+                if matches(&text) {
+                    texts.insert(text.to_string());
+                }
+            }
+        }
+        Ok(texts
+            .into_iter()
+            .map(|text| self.column_term(header, &text))
+            .collect())
+    }
+
+    /// Most frequent terms starting with `prefix`. Cell values complete the last word of
+    /// the prefix, which is folded like them, headers and file names complete the whole
+    /// prefix, case sensitive.
+    pub fn suggest(
+        &self,
+        prefix: &str,
+        options: &SuggestOptions,
+    ) -> tantivy::Result<Vec<Suggestion>> {
+        let folded = fold(prefix);
+        let last_word = folded
+            .rsplit(|c: char| !c.is_alphanumeric())
+            .next()
+            .unwrap_or_default();
+        let mut fields = vec![(self.cell_value_field, last_word)];
+        if options.headers {
+            fields.push((self.cell_ctx_field, prefix));
+        }
+        if options.file_names {
+            fields.push((self.file_name_field, prefix));
+        }
+
+        let searcher = self.index_reader.searcher();
+        let mut suggestions = vec![];
+        for (field, prefix) in fields {
+            if prefix.is_empty() {
+                continue;
+            }
+            let mut doc_freqs: BTreeMap<String, u32> = BTreeMap::new();
+            for segment_reader in searcher.segment_readers() {
+                let inverted_index = segment_reader.inverted_index(field)?;
+                let mut terms = inverted_index
+                    .terms()
+                    .range()
+                    .ge(prefix.as_bytes())
+                    .into_stream()?;
+                let mut scanned = 0;
+                while scanned < MAX_SUGGEST_SCAN && terms.advance() {
+                    if !terms.key().starts_with(prefix.as_bytes()) {
+                        break;
+                    }
+                    scanned += 1;
+                    let text = String::from_utf8_lossy(terms.key()).to_string();
+                    *doc_freqs.entry(text).or_default() += terms.value().doc_freq;
+                }
+            }
+            let field_name = self.schema.get_field_name(field);
+            suggestions.extend(doc_freqs.into_iter().map(|(text, doc_freq)| Suggestion {
+                text,
+                field: field_name.to_string(),
+                doc_freq,
+            }));
+        }
+        // the sort is stable, ties stay in alphabetical order
+        suggestions.sort_by_key(|suggestion| std::cmp::Reverse(suggestion.doc_freq));
+        suggestions.truncate(options.limit);
+        Ok(suggestions)
+    }
+
+    /// Matches `q` in the cells of the column `header` only. Fuzzy and regex queries are
+    /// expanded to the matching terms of the column, up to `MAX_TERM_EXPANSIONS`.
+    pub fn column_query(
+        &self,
+        header: &str,
+        q: &str,
+        query_type: &QueryType,
+        fuzzy: &FuzzyOptions,
+    ) -> Result<Box<dyn Query>, Box<dyn Error>> {
+        if let QueryType::FuzzySearch = query_type {
+            let mut clauses = vec![];
+            for token in self.tokenize(self.cell_value_field, q)? {
+                let terms = self.expand_column_terms(header, |text| fuzzy.matches(&token, text))?;
+                clauses.push(Box::new(terms_union(terms)) as Box<dyn Query>);
+            }
+            return Ok(Box::new(BooleanQuery::intersection(clauses)));
+        }
+        let Some(matcher) =
+            TermMatcher::for_query(self, self.cell_value_field, q, query_type, fuzzy)?
+        else {
+            return Err("the query parser cannot be scoped to a column".into());
+        };
+        if let QueryType::TermQuery = query_type {
+            let mut terms = vec![self.column_term(header, q)];
+            if let Some(number) = parse_number(q) {
+                terms.push(self.column_number_term(header, number));
+            }
+            return Ok(Box::new(terms_union(terms)));
+        }
+        let terms = self.expand_column_terms(header, |text| matcher.matches(text))?;
+        Ok(Box::new(terms_union(terms)))
+    }
+
+    /// Combines the query with the filters, all of them must match.
+    fn filtered_query(
+        &self,
+        field: Field,
+        q: &str,
+        query_type: &QueryType,
+        options: &SearchOptions,
+    ) -> Result<Box<dyn Query>, Box<dyn Error>> {
+        let filters = &options.filters;
+        let fuzzy = &options.fuzzy;
+        let mut clauses = vec![];
+        match (&filters.header, query_type) {
+            // columns are targeted by name in the query, e.g. `row.Supplier:acme`
+            (Some(header), QueryType::QueryParser) => {
+                clauses.push(self.convert_query_type_to_query(field, q, query_type, fuzzy)?);
+                clauses.push(Box::new(TermQuery::new(
+                    Term::from_field_text(self.cell_ctx_field, header),
+                    IndexRecordOption::Basic,
+                )));
+            }
+            (Some(header), _) => clauses.push(self.column_query(header, q, query_type, fuzzy)?),
+            (None, _) => {
+                clauses.push(self.convert_query_type_to_query(field, q, query_type, fuzzy)?)
+            }
+        }
+        if let Some(file_name) = &filters.file_name {
+            clauses.push(self.file_name_query(file_name)?);
+        }
+        if let Some(sheet) = &filters.sheet {
+            clauses.push(Box::new(TermQuery::new(
+                Term::from_field_text(self.sheet_name_field, sheet),
+                IndexRecordOption::Basic,
+            )));
+        }
+        if let Some(file_type) = &filters.file_type {
+            clauses.push(Box::new(TermQuery::new(
+                Term::from_field_text(self.file_type_field, &file_type.to_lowercase()),
+                IndexRecordOption::Basic,
+            )));
+        }
+        if let Some(language) = filters.language {
+            clauses.push(Box::new(TermQuery::new(
+                Term::from_field_text(self.language_field, language.code),
+                IndexRecordOption::Basic,
+            )));
+        }
+        if clauses.len() == 1 {
+            return Ok(clauses.remove(0));
+        }
+        Ok(Box::new(BooleanQuery::intersection(clauses)))
+    }
+
+    /// Searches the index, restricted by the filters of the `options`. Spreadsheet hits
+    /// list the cells that matched.
     pub fn search(
         &self,
         page: usize,
         per_page: usize,
         q: &str,
         query_type: &QueryType,
-    ) -> Result<Vec<NamedFieldDocument>, Box<dyn Error>> {
-        let schema = &self.schema;
-        let query = self.convert_query_type_to_query(q, query_type)?;
+        options: &SearchOptions,
+    ) -> Result<SearchResults, Box<dyn Error>> {
+        let (query, highlighter) = self.prepare_search(q, query_type, options)?;
+        self.search_query(page, per_page, &query, &highlighter, options)
+    }
+
+    /// Query of a search and the highlighter of its hits, see `search`.
+    pub fn prepare_search(
+        &self,
+        q: &str,
+        query_type: &QueryType,
+        options: &SearchOptions,
+    ) -> Result<(Box<dyn Query>, Highlighter), Box<dyn Error>> {
+        let filters = &options.filters;
+        // regexes and columns match the words as written, not their stems
+        let field = match (filters.language, query_type, &filters.header) {
+            (Some(language), QueryType::TermQuery | QueryType::FuzzySearch, None)
+            | (Some(language), QueryType::QueryParser, _) => self.language_value_field(language),
+            _ => self.cell_value_field,
+        };
+        // fuzzy searches are tokenized, the query parser analyzes each field
+        let q = &match query_type {
+            QueryType::TermQuery => self.normalize(field, q)?,
+            QueryType::RegexQuery => fold_pattern(q),
+            QueryType::FuzzySearch | QueryType::QueryParser => q.to_string(),
+        };
+        let query = self.filtered_query(field, q, query_type, options)?;
+        let matcher = TermMatcher::for_query(self, field, q, query_type, &options.fuzzy)?;
+        let header = filters.header.as_deref();
+        let highlighter = Highlighter::new(self, field, matcher, &query, header)?;
+        Ok((query, highlighter))
+    }
+
+    /// Visits every hit of a query in index order, with the cells that matched. The hits
+    /// are neither scored nor kept, so that all of them can be exported.
+    pub fn for_each_hit(
+        &self,
+        query: &dyn Query,
+        highlighter: &Highlighter,
+        mut visit: impl FnMut(&Document, Vec<MatchedCell>) -> io::Result<()>,
+    ) -> Result<u64, Box<dyn Error>> {
+        let searcher = self.index_reader.searcher();
+        let weight = query.weight(EnableScoring::disabled_from_searcher(&searcher))?;
+        let mut count = 0;
+        for segment_reader in searcher.segment_readers() {
+            let store_reader = segment_reader.get_store_reader(1)?;
+            let alive_bitset = segment_reader.alive_bitset();
+            let mut scorer = weight.scorer(segment_reader, 1.0)?;
+            while scorer.doc() != TERMINATED {
+                let doc_id = scorer.doc();
+                scorer.advance();
+                if alive_bitset.is_some_and(|alive_bitset| !alive_bitset.is_alive(doc_id)) {
+                    continue;
+                }
+                let doc = store_reader.get(doc_id)?;
+                let matched_cells =
+                    highlighter.matched_cells(&doc, self.cell_position_field, self.cell_ctx_field);
+                visit(&doc, matched_cells)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Runs a query built by the caller, see `search`. The filters and fuzzy options are
+    /// not used, they are part of the query. When grouped, the pages are made of groups.
+    /// With a cursor, the page starts after it rather than at `page`.
+    pub fn search_query(
+        &self,
+        page: usize,
+        per_page: usize,
+        query: &dyn Query,
+        highlighter: &Highlighter,
+        options: &SearchOptions,
+    ) -> Result<SearchResults, Box<dyn Error>> {
+        let start = Instant::now();
         let searcher = &self.index_reader.searcher();
-        let top_docs = searcher.search(
-            &query,
-            &TopDocs::with_limit(per_page).and_offset(page * per_page),
-        )?;
-        let mut docs = Vec::with_capacity(top_docs.len());
-        for (_score, doc_address) in top_docs.iter() {
-            let retrieved_doc = searcher.doc(*doc_address)?;
-            docs.push(schema.to_named_doc(&retrieved_doc));
+        let facets = self
+            .facet_aggregations(&options.facets)?
+            .map(|aggs| AggregationCollector::from_aggs(aggs, Default::default()));
+        let snippet_max_chars = options.snippet_max_chars;
+        let mut last_hit = None;
+        let (total_hits, hits, groups, facet_results) = match options.group {
+            None if options.sort == SortOptions::default() && options.search_after.is_none() => {
+                let (top_docs, total_hits, facet_results) = searcher.search(
+                    query,
+                    &(
+                        TopDocs::with_limit(per_page).and_offset(page * per_page),
+                        Count,
+                        facets,
+                    ),
+                )?;
+                let mut hits = Vec::with_capacity(top_docs.len());
+                for (score, doc_address) in top_docs {
+                    last_hit = Some((vec![Some(SortValue::Number(score as f64))], doc_address));
+                    hits.push(self.search_hit(
+                        searcher,
+                        score,
+                        doc_address,
+                        highlighter,
+                        snippet_max_chars,
+                    )?);
+                }
+                (total_hits, hits, None, facet_results)
+            }
+            None => {
+                let offset = match options.search_after {
+                    Some(_) => 0,
+                    None => page * per_page,
+                };
+                let collector = SortCollector {
+                    options: options.sort.clone(),
+                    limit: offset + per_page,
+                    after: options.search_after.clone(),
+                };
+                let (sorted_docs, total_hits, facet_results) =
+                    searcher.search(query, &(collector, Count, facets))?;
+                let mut hits = Vec::with_capacity(per_page);
+                for sorted_doc in sorted_docs.into_iter().skip(offset) {
+                    hits.push(self.search_hit(
+                        searcher,
+                        sorted_doc.score,
+                        sorted_doc.doc_address,
+                        highlighter,
+                        snippet_max_chars,
+                    )?);
+                    last_hit = Some((sorted_doc.values, sorted_doc.doc_address));
+                }
+                (total_hits, hits, None, facet_results)
+            }
+            Some(group_options) => {
+                let collector = GroupCollector {
+                    options: group_options,
+                };
+                let (groups, total_hits, facet_results) =
+                    searcher.search(query, &(collector, Count, facets))?;
+                let total_groups = groups.len();
+                let mut page_groups = Vec::with_capacity(per_page);
+                for group in groups.into_iter().skip(page * per_page).take(per_page) {
+                    let mut hits = Vec::with_capacity(group.top_docs.len());
+                    for (score, doc_address) in group.top_docs {
+                        hits.push(self.search_hit(
+                            searcher,
+                            score,
+                            doc_address,
+                            highlighter,
+                            snippet_max_chars,
+                        )?);
+                    }
+                    page_groups.push(SearchGroup {
+                        file_path: group.key.file_path,
+                        sheet: group.key.sheet,
+                        total_hits: group.total_hits,
+                        hits,
+                    });
+                }
+                (
+                    total_hits,
+                    vec![],
+                    Some((total_groups, page_groups)),
+                    facet_results,
+                )
+            }
+        };
+
+        let (total_groups, groups) = groups.unzip();
+        Ok(SearchResults {
+            total_hits,
+            total_groups,
+            page,
+            per_page,
+            took_ms: start.elapsed().as_millis() as u64,
+            next_cursor: last_hit
+                .filter(|_| per_page > 0 && hits.len() == per_page)
+                .map(|(values, doc_address)| SortCursor::new(values, doc_address).encode()),
+            hits,
+            groups,
+            facets: facet_results
+                .map(|results| facet_counts(results.0))
+                .unwrap_or_default(),
+        })
+    }
+
+    fn search_hit(
+        &self,
+        searcher: &Searcher,
+        score: Score,
+        doc_address: DocAddress,
+        highlighter: &Highlighter,
+        snippet_max_chars: Option<usize>,
+    ) -> Result<SearchHit, Box<dyn Error>> {
+        let retrieved_doc = searcher.doc(doc_address)?;
+        let snippet = snippet_max_chars
+            .map(|max_chars| highlighter.snippet(searcher, &retrieved_doc, max_chars))
+            .transpose()?;
+        let matched_cells = highlighter.matched_cells(
+            &retrieved_doc,
+            self.cell_position_field,
+            self.cell_ctx_field,
+        );
+        let row = retrieved_doc
+            .get_first(self.row_field)
+            .and_then(|v| v.as_json())
+            .cloned();
+        let sheet = retrieved_doc
+            .get_first(self.sheet_name_field)
+            .and_then(|v| v.as_text())
+            .map(String::from);
+        let row_number = retrieved_doc
+            .get_first(self.row_number_field)
+            .and_then(|v| v.as_u64());
+        let mut doc = self.schema.to_named_doc(&retrieved_doc);
+        doc.0.remove(ROW_FIELD);
+        doc.0.remove(ROW_VALUES_FIELD);
+        doc.0.remove(ROW_NUMBER_FIELD);
+        Ok(SearchHit {
+            score,
+            doc_address: doc_address.into(),
+            row,
+            sheet,
+            row_number,
+            doc,
+            matched_cells,
+            snippet,
+        })
+    }
+
+    /// Terms aggregations of the facet fields, `None` without any.
+    fn facet_aggregations(
+        &self,
+        facets: &FacetOptions,
+    ) -> Result<Option<Aggregations>, Box<dyn Error>> {
+        if facets.fields.is_empty() {
+            return Ok(None);
+        }
+        let mut aggregations = Map::new();
+        for field in &facets.fields {
+            if !FACET_FIELDS.contains(&field.as_str()) {
+                return Err(format!("{field} is not a facet field").into());
+            }
+            aggregations.insert(
+                field.clone(),
+                json!({ "terms": { "field": field, "size": facets.size } }),
+            );
         }
+        Ok(Some(serde_json::from_value(aggregations.into())?))
+    }
+}
 
-        Ok(docs)
+/// Buckets of the facet aggregations, most frequent values first.
+fn facet_counts(
+    results: impl IntoIterator<Item = (String, AggregationResult)>,
+) -> BTreeMap<String, Vec<FacetCount>> {
+    results
+        .into_iter()
+        .map(|(field, result)| {
+            let counts = match result {
+                AggregationResult::BucketResult(BucketResult::Terms { buckets, .. }) => buckets
+                    .into_iter()
+                    .map(|bucket| FacetCount {
+                        value: bucket.key.to_string(),
+                        count: bucket.doc_count,
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            (field, counts)
+        })
+        .collect()
+}
+
+/// Json path of a column, dots would otherwise split the header in nested objects.
+pub fn column_json_path(header: &str) -> String {
+    header.replace('\\', "\\\\").replace('.', "\\.")
+}
+
+/// Matches the documents containing any of the terms.
+pub fn terms_union(terms: Vec<Term>) -> BooleanQuery {
+    BooleanQuery::union(
+        terms
+            .into_iter()
+            .map(|term| {
+                Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs)) as Box<dyn Query>
+            })
+            .collect(),
+    )
+}
+
+/// Text of a term, the value for terms of JSON fields.
+pub fn term_text(term: &Term) -> Option<&str> {
+    let bytes = term.serialized_value_bytes();
+    match term.typ() {
+        Type::Str => return std::str::from_utf8(bytes).ok(),
+        Type::Json => {}
+        _ => return None,
+    }
+    let end_of_path = bytes.iter().position(|b| *b == JSON_END_OF_PATH)?;
+    match bytes[end_of_path + 1..].split_first() {
+        Some((typ, text)) if *typ == Type::Str.to_code() => std::str::from_utf8(text).ok(),
+        _ => None,
+    }
+}
+
+fn date_or_epoch(doc: &Document, field: Field) -> DateTime {
+    doc.get_first(field)
+        .and_then(|v| v.as_date())
+        .unwrap_or_else(|| DateTime::from_timestamp_secs(0))
+}
+
+/// Name of the folded tokenizer, suffixed with the language of its stemmer so that
+/// changing the language changes the schema.
+fn folded_tokenizer_name(stemmer: Option<Language>) -> String {
+    match stemmer {
+        Some(language) => format!("{FOLDED_TOKENIZER}_{language:?}").to_lowercase(),
+        None => FOLDED_TOKENIZER.to_string(),
+    }
+}
+
+/// Splits on non alphanumeric characters, lowercases and folds accented characters to
+/// ASCII, like `utils::fold`.
+fn folded_tokenizer(stemmer: Option<Language>) -> TextAnalyzer {
+    let builder = TextAnalyzer::builder(SimpleTokenizer::default())
+        .filter(RemoveLongFilter::limit(40))
+        .filter(LowerCaser)
+        .filter(AsciiFoldingFilter);
+    match stemmer {
+        Some(language) => builder.filter(Stemmer::new(language)).build(),
+        None => builder.build(),
+    }
+}
+
+fn sibling_dir(index_dir: &Path, suffix: &str) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let name = index_dir
+        .file_name()
+        .ok_or("index directory has no name")?
+        .to_string_lossy();
+    Ok(index_dir.with_file_name(format!("{name}.{suffix}")))
+}
+
+/// The index served by the application. It can be rebuilt from scratch and swapped
+/// atomically, requests keep using the previous index until the new one is ready.
+#[derive(Clone)]
+pub struct SharedIndex {
+    current: Arc<ArcSwap<FileSearchIndex>>,
+    writes: Arc<RwLock<()>>,
+}
+
+/// The served index, held by a writer. A rebuild waits for every writer to be done,
+/// so that no write is lost when the index is swapped.
+pub struct IndexWriteGuard {
+    file_search_index: Arc<FileSearchIndex>,
+    _permit: OwnedRwLockReadGuard<()>,
+}
+
+impl Deref for IndexWriteGuard {
+    type Target = FileSearchIndex;
+
+    fn deref(&self) -> &FileSearchIndex {
+        &self.file_search_index
+    }
+}
+
+impl SharedIndex {
+    pub fn new(file_search_index: FileSearchIndex) -> SharedIndex {
+        SharedIndex {
+            current: Arc::new(ArcSwap::from_pointee(file_search_index)),
+            writes: Default::default(),
+        }
+    }
+
+    /// Returns the index currently served, to be read from.
+    pub fn current(&self) -> Arc<FileSearchIndex> {
+        self.current.load_full()
+    }
+
+    /// Returns the index currently served to be written to, or `None` while it is rebuilt.
+    pub fn try_write(&self) -> Option<IndexWriteGuard> {
+        let permit = self.writes.clone().try_read_owned().ok()?;
+        Some(IndexWriteGuard {
+            file_search_index: self.current(),
+            _permit: permit,
+        })
+    }
+
+    /// Returns the index currently served to be written to, once a pending rebuild is done.
+    pub fn write(&self) -> impl Future<Output = IndexWriteGuard> + Send + 'static {
+        let index = self.clone();
+        async move {
+            let permit = index.writes.clone().read_owned().await;
+            IndexWriteGuard {
+                file_search_index: index.current(),
+                _permit: permit,
+            }
+        }
+    }
+
+    /// Runs `build` against an empty shadow index and, when it succeeds, replaces the
+    /// served index by the shadow one. When it fails, the shadow index is discarded.
+    /// Writers are kept out until the rebuild is done.
+    pub async fn rebuild<F, Fut, T>(&self, build: F) -> Result<T, Box<dyn Error + Send + Sync>>
+    where
+        F: FnOnce(FileSearchIndex) -> Fut,
+        Fut: Future<Output = Result<T, Box<dyn Error + Send + Sync>>>,
+    {
+        let _rebuild_guard = self.writes.write().await;
+        let shadow = self.current().shadow()?;
+        let shadow_dir = shadow.index_dir.clone();
+        let result = match build(shadow.clone()).await {
+            Ok(res) => match shadow.close().await {
+                Ok(()) => self.swap(&shadow_dir).await.map(|_| res),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        if result.is_err() && shadow_dir.exists() {
+            tracing::warn!("rebuild failed, discarding shadow index {shadow_dir:?}");
+            std::fs::remove_dir_all(&shadow_dir)?;
+        }
+        result
+    }
+
+    async fn swap(&self, shadow_dir: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let current = self.current();
+        let index_dir = current.index_dir.clone();
+        let backup_dir = sibling_dir(&index_dir, "old")?;
+        if backup_dir.exists() {
+            std::fs::remove_dir_all(&backup_dir)?;
+        }
+
+        // no merge may write into the directory once it belongs to the new index, the
+        // current index keeps its files open, so it can still be read once moved
+        current.close().await?;
+        let open = || {
+            FileSearchIndex::new(
+                &index_dir.to_string_lossy(),
+                current.writer_memory_arena,
+                current.stemmer,
+            )
+        };
+        if let Err(e) = std::fs::rename(&index_dir, &backup_dir) {
+            tracing::error!("could not move {index_dir:?} aside, reopening it: {e:?}");
+            self.current.store(Arc::new(open()?));
+            return Err(e.into());
+        }
+        match std::fs::rename(shadow_dir, &index_dir)
+            .map_err(|e| e.into())
+            .and_then(|_| open())
+        {
+            Ok(file_search_index) => {
+                self.current.store(Arc::new(file_search_index));
+                std::fs::remove_dir_all(&backup_dir)?;
+                tracing::info!("index {index_dir:?} swapped");
+                Ok(())
+            }
+            Err(e) => {
+                tracing::error!("could not swap index, restoring {index_dir:?}: {e:?}");
+                if index_dir.exists() {
+                    std::fs::rename(&index_dir, shadow_dir)?;
+                }
+                std::fs::rename(&backup_dir, &index_dir)?;
+                self.current.store(Arc::new(open()?));
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use tantivy::{
+        schema::{Schema, STORED, STRING},
+        Document, Index,
+    };
+
+    use super::{
+        FileSearchIndex, FuzzyOptions, QueryType, SearchOptions, SharedIndex, SuggestOptions,
+        FILE_NAME_FIELD,
+    };
+    use crate::{
+        group::{GroupBy, GroupOptions},
+        sort::{SortBy, SortCursor, SortOptions, SortOrder},
+        test_utils::{metadata, test_index},
+    };
+
+    fn num_docs(index: &SharedIndex) -> u64 {
+        let fsi = index.current();
+        fsi.index_reader.reload().unwrap();
+        fsi.index_reader.searcher().num_docs()
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_swaps_or_rolls_back() {
+        let fsi = test_index();
+        let dir = &fsi.dir;
+        let index_dir = fsi.index_dir.clone();
+        fsi.replace_documents(&metadata("/a.csv"), vec![Document::default()])
+            .await
+            .unwrap();
+        let index = SharedIndex::new(fsi.clone());
+
+        let res = index
+            .rebuild(|shadow| async move {
+                assert_eq!(0, shadow.index_reader.searcher().num_docs());
+                Err::<(), _>("rebuild failed".into())
+            })
+            .await;
+        assert!(res.is_err());
+        assert_eq!(1, num_docs(&index));
+        assert!(!dir.join("index.shadow").exists());
+
+        let replaced = index.current();
+        index
+            .rebuild(|shadow| async move {
+                let docs = vec![Document::default(), Document::default()];
+                shadow.replace_documents(&metadata("/b.csv"), docs).await
+            })
+            .await
+            .unwrap();
+        assert_eq!(2, num_docs(&index));
+        // the replaced index was closed before its directory was reused
+        assert!(replaced
+            .delete_files(&["/a.csv".to_string()])
+            .await
+            .is_err());
+        assert_eq!(1, replaced.index_reader.searcher().num_docs());
+        assert_eq!(index_dir, index.current().index_dir);
+        assert!(!dir.join("index.shadow").exists());
+        assert!(!dir.join("index.old").exists());
+    }
+
+    #[tokio::test]
+    async fn test_search_during_rebuild() {
+        let fsi = test_index();
+        let index = SharedIndex::new(fsi.clone());
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+
+        let rebuild = tokio::spawn({
+            let index = index.clone();
+            async move {
+                index
+                    .rebuild(|_| async move {
+                        started_tx.send(()).unwrap();
+                        done_rx.await?;
+                        Ok(())
+                    })
+                    .await
+            }
+        });
+        started_rx.await.unwrap();
+
+        let search = async {
+            index
+                .current()
+                .search(1, 10, "*", &QueryType::QueryParser, &Default::default())
+        };
+        let res = tokio::time::timeout(std::time::Duration::from_secs(5), search).await;
+        assert_eq!(0, res.unwrap().unwrap().total_hits);
+
+        done_tx.send(()).unwrap();
+        rebuild.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_during_rebuild() {
+        let fsi = test_index();
+        let index = SharedIndex::new(fsi.clone());
+
+        // a pending write delays the rebuild
+        let writer = index.try_write().unwrap();
+        let rebuild = tokio::spawn({
+            let index = index.clone();
+            async move {
+                index
+                    .rebuild(|shadow| async move {
+                        let docs = vec![Document::default(), Document::default()];
+                        shadow.replace_documents(&metadata("/b.csv"), docs).await
+                    })
+                    .await
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!rebuild.is_finished());
+        assert!(index.try_write().is_none());
+        writer
+            .replace_documents(&metadata("/a.csv"), vec![Document::default()])
+            .await
+            .unwrap();
+        drop(writer);
+
+        rebuild.await.unwrap().unwrap();
+        assert_eq!(2, num_docs(&index));
+        let writer = index.try_write().unwrap();
+        writer
+            .replace_documents(&metadata("/a.csv"), vec![Document::default()])
+            .await
+            .unwrap();
+        assert_eq!(3, num_docs(&index));
+    }
+
+    #[tokio::test]
+    async fn test_delete_documents() {
+        let fsi = test_index();
+        for file_path in [
+            "/data/2023/invoices-q1.xlsx",
+            "/data/2023/report.xlsx",
+            "/data/2024/invoices-q1.xlsx",
+            "/data/2024/sub/report.csv",
+            "/data/20245/report.csv",
+        ] {
+            let docs = vec![Document::default(), Document::default()];
+            fsi.replace_documents(&metadata(file_path), docs)
+                .await
+                .unwrap();
+        }
+
+        let query = fsi.file_name_query("invoices*.xlsx").unwrap();
+        assert_eq!(4, fsi.delete_documents(query).await.unwrap());
+        assert_eq!(2, fsi.delete_path("/data/2024").await.unwrap());
+        let query = fsi.file_path_query("/data/2023/report.xlsx");
+        assert_eq!(2, fsi.delete_documents(query).await.unwrap());
+        fsi.index_reader.reload().unwrap();
+        assert_eq!(
+            vec!["/data/20245/report.csv"],
+            fsi.file_doc_counts().unwrap().keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_indexed_files() {
+        let fsi = test_index();
+        let sheet_doc = |sheet_name| {
+            let mut doc = Document::default();
+            doc.add_text(fsi.sheet_name_field, sheet_name);
+            doc
+        };
+        let docs = vec![
+            sheet_doc("Sheet2"),
+            sheet_doc("Sheet1"),
+            sheet_doc("Sheet2"),
+        ];
+        fsi.replace_documents(&metadata("/data/b.xlsx"), docs)
+            .await
+            .unwrap();
+        fsi.replace_documents(&metadata("/data/a.csv"), vec![Document::default()])
+            .await
+            .unwrap();
+        fsi.index_reader.reload().unwrap();
+
+        let (total, files) = fsi.indexed_files(0, 10).unwrap();
+        assert_eq!(2, total);
+        assert_eq!("/data/a.csv", files[0].file_path);
+        assert_eq!(1, files[0].document_count);
+        assert!(files[0].sheets.is_empty());
+        assert_eq!("b.xlsx", files[1].file_name);
+        assert_eq!(3, files[1].document_count);
+        assert_eq!(vec!["Sheet1", "Sheet2"], files[1].sheets);
+        assert_eq!("1970-01-01T00:00:00Z", files[1].modified_at);
+
+        let (total, files) = fsi.indexed_files(1, 1).unwrap();
+        assert_eq!(2, total);
+        assert_eq!(
+            vec!["/data/b.xlsx"],
+            files.iter().map(|f| &f.file_path).collect::<Vec<_>>()
+        );
+        let (total, files) = fsi.indexed_files(usize::MAX, usize::MAX).unwrap();
+        assert_eq!(2, total);
+        assert!(files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_results() {
+        let fsi = test_index();
+        let docs = ["sango", "sango sango", "other", "sango"]
+            .into_iter()
+            .map(|value| {
+                let mut doc = Document::default();
+                doc.add_text(fsi.cell_value_field, value);
+                doc
+            })
+            .collect();
+        fsi.replace_documents(&metadata("/data/a.csv"), docs)
+            .await
+            .unwrap();
+        fsi.index_reader.reload().unwrap();
+
+        let results = fsi
+            .search(
+                1,
+                2,
+                "sango",
+                &QueryType::TermQuery,
+                &SearchOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(3, results.total_hits);
+        assert_eq!((1, 2), (results.page, results.per_page));
+        assert_eq!(1, results.hits.len());
+        assert!(results.hits[0].score > 0.0);
+
+        let first_page = fsi
+            .search(
+                0,
+                2,
+                "sango",
+                &QueryType::TermQuery,
+                &SearchOptions::default(),
+            )
+            .unwrap();
+        assert!(first_page.hits[0].score >= first_page.hits[1].score);
+        assert!(first_page.hits[1].score >= results.hits[0].score);
+    }
+
+    #[tokio::test]
+    async fn test_fuzzy_search() {
+        let fsi = test_index();
+        let docs = ["Regionally membership", "membrane region"]
+            .into_iter()
+            .map(|value| {
+                let mut doc = Document::default();
+                doc.add_text(fsi.cell_value_field, value);
+                doc
+            })
+            .collect();
+        fsi.replace_documents(&metadata("/data/a.pdf"), docs)
+            .await
+            .unwrap();
+        fsi.index_reader.reload().unwrap();
+
+        let total_hits = |q, distance, transpositions, prefix| {
+            let options = SearchOptions {
+                fuzzy: FuzzyOptions {
+                    distance,
+                    transpositions,
+                    prefix,
+                },
+                ..Default::default()
+            };
+            fsi.search(0, 10, q, &QueryType::FuzzySearch, &options)
+                .unwrap()
+                .total_hits
+        };
+        assert_eq!(1, total_hits("Regonaly membersip", 2, true, false));
+        assert_eq!(0, total_hits("Regonaly membersip", 1, true, false));
+        assert_eq!(2, total_hits("memb", 0, true, true));
+        assert_eq!(0, total_hits("memb", 0, true, false));
+        assert_eq!(1, total_hits("mebmership", 1, true, false));
+        assert_eq!(0, total_hits("mebmership", 1, false, false));
+    }
+
+    #[tokio::test]
+    async fn test_folded_search() {
+        let fsi = test_index();
+        let dir = &fsi.dir;
+        let index_dir = fsi.index_dir.clone();
+        let mut doc = Document::default();
+        doc.add_text(fsi.cell_value_field, "Société Générale, Rijksmuseum Café");
+        fsi.replace_documents(&metadata("/data/a.pdf"), vec![doc])
+            .await
+            .unwrap();
+        fsi.index_reader.reload().unwrap();
+
+        for (q, query_type) in [
+            ("societe", QueryType::TermQuery),
+            ("SOCIÉTÉ", QueryType::TermQuery),
+            ("generalle", QueryType::FuzzySearch),
+            ("CAF.", QueryType::RegexQuery),
+            ("rijks\\w+", QueryType::RegexQuery),
+            ("cafe AND generale", QueryType::QueryParser),
+        ] {
+            let hits = fsi
+                .search(0, 10, q, &query_type, &SearchOptions::default())
+                .unwrap();
+            assert_eq!(1, hits.total_hits, "{q}");
+        }
+        fsi.close().await.unwrap();
+
+        // a stemmer changes the schema, the index is moved aside
+        let language = Some(tantivy::tokenizer::Language::French);
+        let outdated = FileSearchIndex::migrate(&index_dir.to_string_lossy(), language).unwrap();
+        assert_eq!(vec![std::path::PathBuf::from("/data/a.pdf")], outdated);
+        assert!(dir.join("index.outdated").exists());
+        let fsi = FileSearchIndex::new(&index_dir.to_string_lossy(), 15_000_000, language).unwrap();
+        fsi.remove_outdated().unwrap();
+        assert!(!dir.join("index.outdated").exists());
+        assert!(
+            FileSearchIndex::migrate(&index_dir.to_string_lossy(), language)
+                .unwrap()
+                .is_empty()
+        );
+        let mut doc = Document::default();
+        doc.add_text(fsi.cell_value_field, "Sociétés générales");
+        fsi.replace_documents(&metadata("/data/a.pdf"), vec![doc])
+            .await
+            .unwrap();
+        fsi.index_reader.reload().unwrap();
+        let hits = fsi
+            .search(
+                0,
+                10,
+                "société",
+                &QueryType::TermQuery,
+                &SearchOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(1, hits.total_hits);
+
+        // an index without file paths is kept, its files couldn't be indexed again
+        let legacy_dir = dir.join("legacy");
+        std::fs::create_dir_all(&legacy_dir).unwrap();
+        let mut schema_builder = Schema::builder();
+        let file_name_field = schema_builder.add_text_field(FILE_NAME_FIELD, STRING | STORED);
+        let legacy = Index::create_in_dir(&legacy_dir, schema_builder.build()).unwrap();
+        let mut legacy_writer = legacy.writer(15_000_000).unwrap();
+        let mut doc = Document::default();
+        doc.add_text(file_name_field, "a.pdf");
+        legacy_writer.add_document(doc).unwrap();
+        legacy_writer.commit().unwrap();
+        assert!(FileSearchIndex::migrate(&legacy_dir.to_string_lossy(), None).is_err());
+        assert!(legacy_dir.exists());
+        assert!(!dir.join("legacy.outdated").exists());
+    }
+
+    #[tokio::test]
+    async fn test_suggest() {
+        let fsi = test_index();
+        let docs = ["Société Générale", "Sociétés", "sociale", "Solvay"]
+            .into_iter()
+            .map(|value| {
+                let mut doc = Document::default();
+                doc.add_text(fsi.cell_value_field, value);
+                doc.add_text(fsi.cell_ctx_field, "Socio");
+                doc
+            })
+            .collect();
+        fsi.replace_documents(&metadata("/data/social.csv"), docs)
+            .await
+            .unwrap();
+        fsi.index_reader.reload().unwrap();
+
+        let suggest = |prefix, headers, file_names| {
+            let options = SuggestOptions {
+                limit: 3,
+                headers,
+                file_names,
+            };
+            fsi.suggest(prefix, &options)
+                .unwrap()
+                .into_iter()
+                .map(|s| (s.text, s.field, s.doc_freq))
+                .collect::<Vec<_>>()
+        };
+        let suggestion = |text: &str, field: &str, doc_freq| (text.into(), field.into(), doc_freq);
+        assert_eq!(
+            vec![
+                suggestion("sociale", "cell_value", 1),
+                suggestion("societe", "cell_value", 1),
+                suggestion("societes", "cell_value", 1),
+            ],
+            suggest("SOCI", false, false)
+        );
+        assert_eq!(
+            vec![suggestion("generale", "cell_value", 1)],
+            suggest("société gé", false, false)
+        );
+        assert_eq!(
+            vec![
+                suggestion("Socio", "cell_ctx", 4),
+                suggestion("sociale", "cell_value", 1),
+                suggestion("societe", "cell_value", 1),
+            ],
+            suggest("Soci", true, true)
+        );
+        assert_eq!(
+            suggestion("social.csv", "file_name", 4),
+            suggest("soc", true, true).remove(0)
+        );
+        assert!(suggest("société ", false, false).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_group_by() {
+        let fsi = test_index();
+        let doc = |sheet: &str, value: &str| {
+            let mut doc = Document::default();
+            doc.add_text(fsi.sheet_name_field, sheet);
+            doc.add_text(fsi.cell_value_field, value);
+            doc
+        };
+        let workbook = vec![
+            doc("2023", "acme"),
+            doc("2023", "acme invoice"),
+            doc("2024", "acme"),
+            doc("2024", "acme acme"),
+            doc("2024", "globex"),
+        ];
+        fsi.replace_documents(&metadata("/data/a.xlsx"), workbook)
+            .await
+            .unwrap();
+        fsi.replace_documents(
+            &metadata("/data/b.xlsx"),
+            vec![doc("2024", "acme acme acme")],
+        )
+        .await
+        .unwrap();
+        fsi.index_reader.reload().unwrap();
+
+        let search = |page, by| {
+            let options = SearchOptions {
+                group: Some(GroupOptions {
+                    by,
+                    hits_per_group: 2,
+                }),
+                ..Default::default()
+            };
+            fsi.search(page, 2, "acme", &QueryType::TermQuery, &options)
+                .unwrap()
+        };
+        let results = search(0, GroupBy::File);
+        assert_eq!(5, results.total_hits);
+        assert_eq!(Some(2), results.total_groups);
+        assert!(results.hits.is_empty());
+        let groups = results.groups.unwrap();
+        let summary = groups
+            .iter()
+            .map(|g| (g.file_path.as_str(), g.total_hits, g.hits.len()))
+            .collect::<Vec<_>>();
+        // the shorter the cell, the better the score
+        assert_eq!(
+            vec![("/data/a.xlsx", 4, 2), ("/data/b.xlsx", 1, 1)],
+            summary
+        );
+        let best = &groups[0].hits;
+        assert!(best[0].score >= best[1].score);
+
+        let results = search(1, GroupBy::Sheet);
+        assert_eq!(Some(3), results.total_groups);
+        let groups = results.groups.unwrap();
+        assert_eq!(1, groups.len());
+        assert_eq!("/data/b.xlsx", groups[0].file_path);
+        assert_eq!(Some("2024"), groups[0].sheet.as_deref());
+        assert_eq!(1, groups[0].total_hits);
+    }
+
+    #[tokio::test]
+    async fn test_sort() {
+        let fsi = test_index();
+        let doc = |row_number: u64, row: serde_json::Value| {
+            let mut doc = Document::default();
+            doc.add_text(fsi.cell_value_field, "acme");
+            doc.add_u64(fsi.row_number_field, row_number);
+            doc.add_json_object(fsi.row_field, row.as_object().unwrap().clone());
+            doc.add_json_object(fsi.row_values_field, row.as_object().unwrap().clone());
+            doc
+        };
+        let rows = vec![
+            doc(2, json!({"Amount": 30.0, "Due": "2024-03-01T00:00:00Z"})),
+            doc(3, json!({"Amount": 10.0, "Due": "2024-01-15T00:00:00Z"})),
+            doc(4, json!({"Amount": "n/a"})),
+        ];
+        fsi.replace_documents(&metadata("/data/b.csv"), rows)
+            .await
+            .unwrap();
+        fsi.replace_documents(
+            &metadata("/data/a.csv"),
+            vec![doc(
+                2,
+                json!({"Amount": 20.0, "Due": "2024-02-01T00:00:00Z"}),
+            )],
+        )
+        .await
+        .unwrap();
+        fsi.index_reader.reload().unwrap();
+
+        let search = |page, sort: &str, order: Option<SortOrder>| {
+            let by: SortBy = sort.parse().unwrap();
+            let options = SearchOptions {
+                sort: SortOptions {
+                    order: order.unwrap_or_else(|| by.default_order()),
+                    by,
+                },
+                ..Default::default()
+            };
+            let results = fsi
+                .search(page, 2, "acme", &QueryType::TermQuery, &options)
+                .unwrap();
+            assert_eq!(4, results.total_hits);
+            results
+                .hits
+                .into_iter()
+                .map(|hit| {
+                    let row = hit.row.unwrap();
+                    format!("{}:{}", row["Amount"], hit.row_number.unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        // values missing or not numbers come last
+        assert_eq!(
+            vec!["30.0:2", "20.0:2"],
+            search(0, "column:Amount", Some(SortOrder::Desc))
+        );
+        assert_eq!(
+            vec!["10.0:3", "\"n/a\":4"],
+            search(1, "column:Amount", Some(SortOrder::Desc))
+        );
+        assert_eq!(vec!["10.0:3", "20.0:2"], search(0, "column:Due", None));
+        assert_eq!(vec!["20.0:2", "30.0:2"], search(0, "row_number", None));
+        assert_eq!(vec!["10.0:3", "\"n/a\":4"], search(1, "row_number", None));
+        assert_eq!(vec!["20.0:2", "30.0:2"], search(0, "file_name", None));
+        // ties are sorted by document, whatever the order
+        assert_eq!(
+            search(0, "modified_at", Some(SortOrder::Asc)),
+            search(0, "modified_at", Some(SortOrder::Desc))
+        );
+
+        // a cursor pages like the page number
+        for sort in ["relevance", "file_name", "row_number", "column:Amount"] {
+            let by: SortBy = sort.parse().unwrap();
+            let mut options = SearchOptions {
+                sort: SortOptions {
+                    order: by.default_order(),
+                    by,
+                },
+                ..Default::default()
+            };
+            let search = |per_page, options: &SearchOptions| {
+                fsi.search(0, per_page, "acme", &QueryType::TermQuery, options)
+                    .unwrap()
+            };
+            let expected = search(10, &options)
+                .hits
+                .iter()
+                .map(|hit| (hit.doc_address.segment_ord, hit.doc_address.doc_id))
+                .collect::<Vec<_>>();
+            let mut addresses = vec![];
+            loop {
+                let results = search(3, &options);
+                addresses.extend(
+                    results
+                        .hits
+                        .iter()
+                        .map(|hit| (hit.doc_address.segment_ord, hit.doc_address.doc_id)),
+                );
+                let Some(cursor) = results.next_cursor else {
+                    break;
+                };
+                options.search_after = Some(SortCursor::decode(&cursor, &options.sort).unwrap());
+            }
+            assert_eq!(expected, addresses, "{sort}");
+        }
     }
 }
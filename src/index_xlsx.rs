@@ -1,19 +1,26 @@
 use std::{error::Error, path::PathBuf};
 
 use calamine::{open_workbook_auto, DataType, Reader};
+use serde_json::Map;
 use tantivy::Document;
 
-use crate::{index_tantivy::FileSearchIndex, utils::convert_row_column_to_letter};
+use crate::{
+    index_tantivy::{FileMetadata, FileSearchIndex},
+    language::DocLanguage,
+    utils::{column_header, convert_row_column_to_letter, insert_cell, naive_to_date, CellValue},
+};
 
 pub async fn index_xlsx_file(
     file_search_index: FileSearchIndex,
     path_to_xlsx: impl Into<PathBuf>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let path_to_xlsx = path_to_xlsx.into();
+    language: Option<&'static DocLanguage>,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let path_to_xlsx = path_to_xlsx.into().canonicalize()?;
+    let metadata = FileMetadata::from_path(&path_to_xlsx)?;
     let mut workbook = open_workbook_auto(&path_to_xlsx)?;
     let sheets = workbook.sheet_names().to_owned();
 
-    let mut index_writer = file_search_index.index_writer.lock().await;
+    let mut docs = Vec::new();
     for sheet_name in sheets {
         if let Some(Ok(range)) = workbook.worksheet_range(&sheet_name) {
             tracing::info!("indexing start for sheet {sheet_name}.");
@@ -29,19 +36,16 @@ pub async fn index_xlsx_file(
                 .map(|c| c.to_string())
                 .collect::<Vec<String>>();
 
+            let mut sheet_docs = Vec::new();
             for (row_idx, row) in range.rows().skip(1).enumerate() {
                 if row.iter().all(|c| c == &DataType::Empty) {
                     continue;
                 }
                 let mut doc = Document::default();
-                doc.add_text(
-                    file_search_index.file_name_field,
-                    path_to_xlsx
-                        .file_name()
-                        .ok_or("file not found")?
-                        .to_string_lossy(),
-                );
                 doc.add_text(file_search_index.sheet_name_field, &sheet_name);
+                doc.add_u64(file_search_index.row_number_field, row_idx as u64 + 2);
+                let mut row_object = Map::new();
+                let mut row_values = Map::new();
                 for (column, cell) in row.iter().enumerate() {
                     if &DataType::Empty == cell {
                         continue;
@@ -50,18 +54,37 @@ pub async fn index_xlsx_file(
                         file_search_index.cell_position_field,
                         convert_row_column_to_letter(row_idx + 1, column), // we skip one row
                     );
-                    doc.add_text(file_search_index.cell_ctx_field, &labels[column]);
+                    let header = column_header(&labels, column);
+                    doc.add_text(file_search_index.cell_ctx_field, &header);
                     doc.add_text(file_search_index.cell_value_field, cell.to_string());
+                    let value = match cell {
+                        DataType::Int(_) | DataType::Float(_) => {
+                            cell.as_f64().map(CellValue::Number)
+                        }
+                        DataType::DateTime(_) | DataType::DateTimeIso(_) => cell
+                            .as_datetime()
+                            .map(|d| CellValue::Date(naive_to_date(d))),
+                        _ => None,
+                    }
+                    .unwrap_or_else(|| CellValue::Text(cell.to_string()));
+                    file_search_index.add_cell_value(&mut doc, &value);
+                    insert_cell(&mut row_object, &header, column, cell.to_string().into());
+                    insert_cell(&mut row_values, &header, column, value.to_json());
                 }
+                doc.add_json_object(file_search_index.row_field, row_object);
+                doc.add_json_object(file_search_index.row_values_field, row_values);
 
-                index_writer.add_document(doc)?;
+                sheet_docs.push(doc);
             }
+            // the language is detected per sheet
+            file_search_index.set_language(&mut sheet_docs, language);
+            docs.append(&mut sheet_docs);
             tracing::info!("indexing done.");
         }
     }
-    index_writer.commit()?;
+    let count = file_search_index.replace_documents(&metadata, docs).await?;
 
-    Ok(())
+    Ok(count)
 }
 
 #[cfg(test)]
@@ -81,9 +104,10 @@ mod test {
         let file_search_index = FileSearchIndex::new(
             &std::env::var("INDEX_DIR_PATH").unwrap_or_else(|_| "/tmp/__tantivy_data".to_string()),
             50_000_000,
+            None,
         )
         .unwrap();
-        index_xlsx_file(file_search_index, "test2.xlsx")
+        index_xlsx_file(file_search_index, "test2.xlsx", None)
             .await
             .unwrap();
     }
@@ -93,6 +117,7 @@ mod test {
         let file_search_index = FileSearchIndex::new(
             &std::env::var("INDEX_DIR_PATH").unwrap_or_else(|_| "/tmp/__tantivy_data".to_string()),
             50_000_000,
+            None,
         )
         .unwrap();
 
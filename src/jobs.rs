@@ -0,0 +1,243 @@
+use std::{collections::HashMap, error::Error, future::Future, sync::Arc, time::Instant};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::walk::SkippedEntry;
+
+/// Number of finished jobs kept in memory, the oldest ones are dropped first.
+const MAX_FINISHED_JOBS: usize = 1000;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum JobKind {
+    Index,
+    Reindex,
+    Watch,
+    Sync,
+}
+
+/// Outcome of a successful job.
+#[derive(Default, Debug)]
+pub struct JobReport {
+    pub documents_added: u64,
+    pub files_indexed: usize,
+    pub files_unchanged: usize,
+    pub files_deleted: usize,
+    pub skipped: Vec<SkippedEntry>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: JobKind,
+    pub status: JobStatus,
+    pub path: String,
+    pub documents_added: u64,
+    pub files_indexed: usize,
+    pub files_unchanged: usize,
+    pub files_deleted: usize,
+    pub skipped: Vec<SkippedEntry>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Local>,
+    pub started_at: Option<DateTime<Local>>,
+    pub finished_at: Option<DateTime<Local>>,
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Clone, Default)]
+pub struct JobRegistry {
+    jobs: Arc<RwLock<HashMap<Uuid, Job>>>,
+}
+
+impl JobRegistry {
+    /// Registers a new job and runs `task` in the background, tracking its status.
+    pub async fn spawn<F>(&self, kind: JobKind, path: impl Into<String>, task: F) -> Uuid
+    where
+        F: Future<Output = Result<JobReport, Box<dyn Error + Send + Sync>>> + Send + 'static,
+    {
+        self.spawn_when(kind, path, async {}, |()| task).await
+    }
+
+    /// Registers a new job that stays queued until `ready` resolves, e.g. with a write
+    /// permit, then runs the task built from its output in the background.
+    pub async fn spawn_when<R, T, F>(
+        &self,
+        kind: JobKind,
+        path: impl Into<String>,
+        ready: R,
+        task: T,
+    ) -> Uuid
+    where
+        R: Future + Send + 'static,
+        R::Output: Send,
+        T: FnOnce(R::Output) -> F + Send + 'static,
+        F: Future<Output = Result<JobReport, Box<dyn Error + Send + Sync>>> + Send + 'static,
+    {
+        let id = Uuid::new_v4();
+        let job = Job {
+            id,
+            kind,
+            status: JobStatus::Queued,
+            path: path.into(),
+            documents_added: 0,
+            files_indexed: 0,
+            files_unchanged: 0,
+            files_deleted: 0,
+            skipped: vec![],
+            error: None,
+            created_at: Local::now(),
+            started_at: None,
+            finished_at: None,
+            duration_ms: None,
+        };
+        self.jobs.write().await.insert(id, job);
+
+        let registry = self.clone();
+        tokio::spawn(async move {
+            let ready = ready.await;
+            registry
+                .update(id, |job| {
+                    job.status = JobStatus::Running;
+                    job.started_at = Some(Local::now());
+                })
+                .await;
+            let start = Instant::now();
+            let result = task(ready).await;
+            registry
+                .update(id, |job| {
+                    job.finished_at = Some(Local::now());
+                    job.duration_ms = Some(start.elapsed().as_millis() as u64);
+                    match result {
+                        Ok(report) => {
+                            job.status = JobStatus::Succeeded;
+                            job.documents_added = report.documents_added;
+                            job.files_indexed = report.files_indexed;
+                            job.files_unchanged = report.files_unchanged;
+                            job.files_deleted = report.files_deleted;
+                            job.skipped = report.skipped;
+                        }
+                        Err(e) => {
+                            tracing::error!("job {id} failed: {e:?}");
+                            job.status = JobStatus::Failed;
+                            job.error = Some(e.to_string());
+                        }
+                    }
+                })
+                .await;
+            registry.prune().await;
+        });
+        id
+    }
+
+    pub async fn get(&self, id: &Uuid) -> Option<Job> {
+        self.jobs.read().await.get(id).cloned()
+    }
+
+    /// Returns all known jobs, most recent first.
+    pub async fn list(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self.jobs.read().await.values().cloned().collect();
+        jobs.sort_by_key(|j| std::cmp::Reverse(j.created_at));
+        jobs
+    }
+
+    async fn update(&self, id: Uuid, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.write().await.get_mut(&id) {
+            f(job);
+        }
+    }
+
+    async fn prune(&self) {
+        let mut jobs = self.jobs.write().await;
+        let mut finished: Vec<(DateTime<Local>, Uuid)> = jobs
+            .values()
+            .filter(|j| matches!(j.status, JobStatus::Succeeded | JobStatus::Failed))
+            .map(|j| (j.created_at, j.id))
+            .collect();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        for (_, id) in finished.iter().take(finished.len() - MAX_FINISHED_JOBS) {
+            jobs.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{JobKind, JobRegistry, JobReport, JobStatus};
+
+    #[tokio::test]
+    async fn test_job_lifecycle() {
+        let registry = JobRegistry::default();
+        let ok = registry
+            .spawn(JobKind::Index, "/tmp/ok.csv", async {
+                Ok(JobReport {
+                    documents_added: 3,
+                    ..Default::default()
+                })
+            })
+            .await;
+        let ko = registry
+            .spawn(JobKind::Index, "/tmp/ko.csv", async {
+                Err("corrupt file".into())
+            })
+            .await;
+        for _ in 0..100 {
+            let jobs = registry.list().await;
+            if jobs.iter().all(|j| j.finished_at.is_some()) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let ok = registry.get(&ok).await.unwrap();
+        assert_eq!(JobStatus::Succeeded, ok.status);
+        assert_eq!(3, ok.documents_added);
+        let ko = registry.get(&ko).await.unwrap();
+        assert_eq!(JobStatus::Failed, ko.status);
+        assert_eq!(Some("corrupt file".to_string()), ko.error);
+    }
+
+    #[tokio::test]
+    async fn test_job_queued_until_ready() {
+        let registry = JobRegistry::default();
+        let (ready_tx, ready_rx) = tokio::sync::oneshot::channel::<u64>();
+        let id = registry
+            .spawn_when(JobKind::Watch, "/tmp/a.csv", ready_rx, |ready| async move {
+                Ok(JobReport {
+                    documents_added: ready?,
+                    ..Default::default()
+                })
+            })
+            .await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let job = registry.get(&id).await.unwrap();
+        assert_eq!(JobStatus::Queued, job.status);
+        assert_eq!(None, job.started_at);
+
+        ready_tx.send(2).unwrap();
+        for _ in 0..100 {
+            if registry.get(&id).await.unwrap().finished_at.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let job = registry.get(&id).await.unwrap();
+        assert_eq!(JobStatus::Succeeded, job.status);
+        assert_eq!(2, job.documents_added);
+    }
+}
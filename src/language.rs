@@ -0,0 +1,97 @@
+use tantivy::tokenizer::{
+    AsciiFoldingFilter, Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer,
+    StopWordFilter, TextAnalyzer,
+};
+use whatlang::{Detector, Lang};
+
+/// Text longer than this is truncated before detecting its language.
+pub const MAX_DETECTION_CHARS: usize = 10_000;
+
+/// A language the cell values can be analyzed in, with its stemmer and stop words.
+pub struct DocLanguage {
+    /// ISO 639-1 code, e.g. `fr`.
+    pub code: &'static str,
+    pub stemmer: Language,
+    lang: Lang,
+}
+
+pub static LANGUAGES: [DocLanguage; 6] = [
+    DocLanguage {
+        code: "en",
+        stemmer: Language::English,
+        lang: Lang::Eng,
+    },
+    DocLanguage {
+        code: "fr",
+        stemmer: Language::French,
+        lang: Lang::Fra,
+    },
+    DocLanguage {
+        code: "nl",
+        stemmer: Language::Dutch,
+        lang: Lang::Nld,
+    },
+    DocLanguage {
+        code: "de",
+        stemmer: Language::German,
+        lang: Lang::Deu,
+    },
+    DocLanguage {
+        code: "es",
+        stemmer: Language::Spanish,
+        lang: Lang::Spa,
+    },
+    DocLanguage {
+        code: "it",
+        stemmer: Language::Italian,
+        lang: Lang::Ita,
+    },
+];
+
+impl DocLanguage {
+    pub fn from_code(code: &str) -> Option<&'static DocLanguage> {
+        LANGUAGES
+            .iter()
+            .find(|language| language.code.eq_ignore_ascii_case(code))
+    }
+
+    /// Detects the language of `text` among the supported ones, `None` when the
+    /// detection is not reliable, e.g. for a few words.
+    pub fn detect(text: &str) -> Option<&'static DocLanguage> {
+        let text = match text.char_indices().nth(MAX_DETECTION_CHARS) {
+            Some((end, _)) => &text[..end],
+            None => text,
+        };
+        let detector = Detector::with_allowlist(LANGUAGES.iter().map(|l| l.lang).collect());
+        let info = detector.detect(text).filter(|info| info.is_reliable())?;
+        LANGUAGES
+            .iter()
+            .find(|language| language.lang == info.lang())
+    }
+
+    /// Field holding the cell values written in this language, e.g. `cell_value_fr`.
+    pub fn field_name(&self) -> String {
+        format!("cell_value_{}", self.code)
+    }
+
+    pub fn tokenizer_name(&self) -> String {
+        format!("folded_{}", self.code)
+    }
+
+    /// Like the folded tokenizer, stop words are removed and words are stemmed before
+    /// being folded, both work on accented words.
+    pub fn tokenizer(&self) -> TextAnalyzer {
+        let builder = TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(RemoveLongFilter::limit(40))
+            .filter(LowerCaser)
+            .dynamic();
+        let builder = match StopWordFilter::new(self.stemmer) {
+            Some(stop_words) => builder.filter_dynamic(stop_words),
+            None => builder,
+        };
+        builder
+            .filter_dynamic(Stemmer::new(self.stemmer))
+            .filter_dynamic(AsciiFoldingFilter)
+            .build()
+    }
+}
@@ -1,26 +1,62 @@
-use std::{env::var, error::Error, net::SocketAddr, path::PathBuf, str::FromStr};
+use std::{
+    env::var,
+    error::Error,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    body::StreamBody,
+    extract::{FromRef, Query, State},
+    http::{header, StatusCode},
     response::{ErrorResponse, IntoResponse},
     routing::{get, post},
     Json, Router,
 };
 use chrono::Local;
 use index_tantivy::QueryType;
-use serde::Deserialize;
+use jobs::{JobKind, JobRegistry, JobReport};
+use serde::{Deserialize, Serialize};
+use tantivy::tokenizer::Language;
 use time::{macros::format_description, UtcOffset};
+use tokio_util::io::{ReaderStream, SyncIoBridge};
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 use tracing::Level;
 use tracing_subscriber::{fmt::time::OffsetTime, EnvFilter, FmtSubscriber};
+use walk::{walk_directory, PathMatcher, SkippedEntry, WalkOptions};
+use watch::WatchConfig;
+
+use uuid::Uuid;
 
-use crate::index_tantivy::FileSearchIndex;
+use crate::export::{export, ExportFormat};
+use crate::group::{GroupBy, GroupOptions};
+use crate::highlight::{Highlighter, DEFAULT_SNIPPET_MAX_CHARS};
+use crate::index_tantivy::{
+    FacetOptions, FileSearchIndex, FuzzyOptions, IndexWriteGuard, IndexedFile, SearchFilters,
+    SearchOptions, SharedIndex, SuggestOptions, Suggestion, FACET_FIELDS, MAX_FUZZY_DISTANCE,
+};
+use crate::language::DocLanguage;
+use crate::query_dsl::QueryNode;
+use crate::sort::{SortBy, SortCursor, SortOptions, SortOrder};
+mod export;
+mod group;
+mod highlight;
 mod index_csv;
 mod index_pdf;
 mod index_tantivy;
 mod index_xlsx;
+mod jobs;
+mod language;
+mod query_dsl;
+mod sort;
+mod sync;
+#[cfg(test)]
+mod test_utils;
 mod utils;
+mod walk;
+mod watch;
 
 pub static CORS_ALLOW_ORIGIN: &str = "CORS_ALLOW_ORIGIN";
 pub static BODY_SIZE_LIMIT: &str = "BODY_SIZE_LIMIT";
@@ -32,6 +68,18 @@ pub static SERVICE_DATA_VOLUME: &str = "SERVICE_DATA_VOLUME";
 pub static SERVICE_APPLICATION_NAME: &str = "SERVICE_APPLICATION_NAME";
 pub static SERVICE_COLLECTION_NAME: &str = "SERVICE_COLLECTION_NAME";
 pub static INDEX_DIR_PATH: &str = "INDEX_DIR_PATH";
+pub static WATCH_DIRECTORIES: &str = "WATCH_DIRECTORIES";
+pub static WATCH_DEBOUNCE_MS: &str = "WATCH_DEBOUNCE_MS";
+pub static WATCH_EXCLUDE: &str = "WATCH_EXCLUDE";
+pub static STEMMER_LANGUAGE: &str = "STEMMER_LANGUAGE";
+pub static MAX_RESULT_WINDOW: &str = "MAX_RESULT_WINDOW";
+
+const MAX_SUGGESTIONS: usize = 100;
+const DEFAULT_FACET_SIZE: usize = 10;
+const MAX_FACET_SIZE: usize = 1000;
+const DEFAULT_HITS_PER_GROUP: usize = 3;
+const MAX_HITS_PER_GROUP: usize = 100;
+const EXPORT_BUFFER_SIZE: usize = 64 * 1024;
 
 #[derive(Deserialize)]
 pub struct SearchRequest {
@@ -39,16 +87,220 @@ pub struct SearchRequest {
     per_page: usize,
     q: String,
     query_type: QueryType,
+    #[serde(default)]
+    highlight: bool,
+    snippet_max_chars: Option<usize>,
+    /// Glob pattern, e.g. `invoices*`
+    file_name: Option<String>,
+    sheet: Option<String>,
+    header: Option<String>,
+    file_type: Option<String>,
+    /// Language code, e.g. `fr`.
+    language: Option<String>,
+    distance: Option<u8>,
+    transpositions: Option<bool>,
+    #[serde(default)]
+    prefix: bool,
+    /// Comma separated facet fields, e.g. `file_name,sheet_name`
+    facets: Option<String>,
+    facet_size: Option<usize>,
+    group_by: Option<GroupBy>,
+    hits_per_group: Option<usize>,
+    /// `relevance`, `file_name`, `modified_at`, `row_number` or `column:<header>`
+    sort: Option<String>,
+    order: Option<SortOrder>,
+    /// `next_cursor` of the previous page, `page` is then ignored.
+    search_after: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct QuerySearchRequest {
+    page: usize,
+    per_page: usize,
+    query: QueryNode,
+    #[serde(default)]
+    highlight: bool,
+    snippet_max_chars: Option<usize>,
+    #[serde(default)]
+    facets: Vec<String>,
+    facet_size: Option<usize>,
+    group_by: Option<GroupBy>,
+    hits_per_group: Option<usize>,
+    /// `relevance`, `file_name`, `modified_at`, `row_number` or `column:<header>`
+    sort: Option<String>,
+    order: Option<SortOrder>,
+    /// `next_cursor` of the previous page, `page` is then ignored.
+    search_after: Option<String>,
+}
+
+impl SearchRequest {
+    fn validate(&self, max_result_window: usize) -> Result<(), StatusCode> {
+        check_result_window(
+            self.page,
+            self.per_page,
+            self.search_after.is_some(),
+            max_result_window,
+        )
+    }
+}
+
+impl QuerySearchRequest {
+    fn validate(&self, max_result_window: usize) -> Result<(), StatusCode> {
+        check_result_window(
+            self.page,
+            self.per_page,
+            self.search_after.is_some(),
+            max_result_window,
+        )
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportRequest {
+    q: String,
+    query_type: QueryType,
+    format: ExportFormat,
+    /// Glob pattern, e.g. `invoices*`
+    file_name: Option<String>,
+    sheet: Option<String>,
+    header: Option<String>,
+    file_type: Option<String>,
+    /// Language code, e.g. `fr`.
+    language: Option<String>,
+    distance: Option<u8>,
+    transpositions: Option<bool>,
+    #[serde(default)]
+    prefix: bool,
 }
 
 #[derive(Deserialize)]
 pub struct IndexRequest {
     file_path: String,
+    /// Language code, e.g. `fr`, detected when missing.
+    language: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct ReindexRequest {
     directory_path: String,
+    max_depth: Option<usize>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    follow_symlinks: bool,
+}
+
+impl ReindexRequest {
+    fn validate(&self) -> Result<(PathBuf, WalkOptions), StatusCode> {
+        let path = PathBuf::from(&self.directory_path);
+        if !path.exists() || !path.is_dir() {
+            tracing::error!("path {path:?} doesn't exist or is not a directory");
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        PathMatcher::new(&self.include, &self.exclude).map_err(|e| {
+            tracing::error!("invalid glob pattern: {e:?}");
+            StatusCode::BAD_REQUEST
+        })?;
+        let walk_options = WalkOptions {
+            max_depth: self.max_depth,
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            follow_symlinks: self.follow_symlinks,
+        };
+        Ok((path, walk_options))
+    }
+}
+
+pub type SyncRequest = ReindexRequest;
+
+/// Exactly one of the fields must be set.
+#[derive(Deserialize)]
+pub struct DeleteRequest {
+    file_path: Option<String>,
+    directory_path: Option<String>,
+    /// Glob pattern, e.g. `invoices*.xlsx`
+    file_name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DeleteResponse {
+    documents_deleted: u64,
+}
+
+#[derive(Deserialize)]
+pub struct SuggestRequest {
+    prefix: String,
+    #[serde(default = "default_suggest_limit")]
+    limit: usize,
+    #[serde(default)]
+    headers: bool,
+    #[serde(default)]
+    file_names: bool,
+}
+
+fn default_suggest_limit() -> usize {
+    10
+}
+
+#[derive(Serialize)]
+pub struct SuggestResponse {
+    suggestions: Vec<Suggestion>,
+}
+
+#[derive(Deserialize)]
+pub struct FilesRequest {
+    #[serde(default)]
+    page: usize,
+    #[serde(default = "default_per_page")]
+    per_page: usize,
+}
+
+fn default_per_page() -> usize {
+    20
+}
+
+#[derive(Serialize)]
+pub struct FilesResponse {
+    total: usize,
+    page: usize,
+    per_page: usize,
+    files: Vec<IndexedFile>,
+}
+
+#[derive(Serialize)]
+pub struct JobCreated {
+    job_id: Uuid,
+}
+
+/// Searches can page through at most that many hits, deeper pages need a cursor.
+#[derive(Clone, Copy)]
+pub struct MaxResultWindow(usize);
+
+#[derive(Clone)]
+pub struct AppState {
+    index: SharedIndex,
+    jobs: JobRegistry,
+    max_result_window: MaxResultWindow,
+}
+
+impl FromRef<AppState> for SharedIndex {
+    fn from_ref(state: &AppState) -> Self {
+        state.index.clone()
+    }
+}
+
+impl FromRef<AppState> for JobRegistry {
+    fn from_ref(state: &AppState) -> Self {
+        state.jobs.clone()
+    }
+}
+
+impl FromRef<AppState> for MaxResultWindow {
+    fn from_ref(state: &AppState) -> Self {
+        state.max_result_window
+    }
 }
 
 #[tokio::main]
@@ -60,27 +312,251 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .parse::<usize>()?;
     let port = var(SERVICE_PORT).unwrap_or_else(|_| String::from("8080"));
     let app_name = var(SERVICE_APPLICATION_NAME).unwrap_or_else(|_| String::from("file-search"));
-    let file_search_index = FileSearchIndex::new(
-        &var(INDEX_DIR_PATH).unwrap_or_else(|_| "/tmp/__tantivy_data".to_string()),
-        index_writer_size,
-    )?;
+    let index_dir = var(INDEX_DIR_PATH).unwrap_or_else(|_| "/tmp/__tantivy_data".to_string());
+    let max_result_window = var(MAX_RESULT_WINDOW)
+        .unwrap_or_else(|_| String::from("10000"))
+        .parse::<usize>()?;
+    let stemmer = match var(STEMMER_LANGUAGE) {
+        Ok(language) => Some(parse_language(&language)?),
+        Err(_) => None,
+    };
+    let outdated_files =
+        FileSearchIndex::migrate(&index_dir, stemmer).map_err(|e| e as Box<dyn Error>)?;
+    let file_search_index = FileSearchIndex::new(&index_dir, index_writer_size, stemmer)
+        .map_err(|e| e as Box<dyn Error>)?;
+    file_search_index
+        .remove_outdated()
+        .map_err(|e| e as Box<dyn Error>)?;
+
+    let index = SharedIndex::new(file_search_index);
+    let jobs = JobRegistry::default();
+
+    if !outdated_files.is_empty() {
+        jobs.spawn_when(JobKind::Index, index_dir, index.write(), |fsi| async move {
+            let mut report = JobReport::default();
+            for file in outdated_files {
+                match index_path(file.clone(), fsi.clone(), None).await {
+                    Ok(documents_added) => {
+                        report.documents_added += documents_added;
+                        report.files_indexed += 1;
+                    }
+                    Err(e) => {
+                        tracing::error!("could not index {file:?} again: {e:?}");
+                        report
+                            .skipped
+                            .push(SkippedEntry::new(&file, format!("failed: {e}")));
+                    }
+                }
+            }
+            Ok(report)
+        })
+        .await;
+    }
+
+    let _watcher = match var(WATCH_DIRECTORIES) {
+        Ok(directories) => {
+            let config = WatchConfig {
+                directories: split_list(&directories).map(PathBuf::from).collect(),
+                debounce: Duration::from_millis(
+                    var(WATCH_DEBOUNCE_MS)
+                        .unwrap_or_else(|_| String::from("2000"))
+                        .parse::<u64>()?,
+                ),
+                exclude: split_list(
+                    &var(WATCH_EXCLUDE).unwrap_or_else(|_| String::from("~$*,.~lock.*")),
+                )
+                .map(String::from)
+                .collect(),
+            };
+            Some(
+                watch::watch(config, index.clone(), jobs.clone())
+                    .map_err(|e| e as Box<dyn Error>)?,
+            )
+        }
+        Err(_) => None,
+    };
 
     let addr = SocketAddr::from_str(&format!("{host}:{port}"))?;
     tracing::info!("{app_name} :: listening on {:?}", addr);
     let app = Router::new()
-        .route("/index", post(post_index))
+        .route("/index", post(post_index).delete(delete_index))
         .route("/reindex", post(reindex_from_directory))
-        .route("/search", get(get_search))
+        .route("/sync", post(sync_from_directory))
+        .route("/search", get(get_search).post(post_search))
+        .route("/search/export", get(get_search_export))
+        .route("/suggest", get(get_suggest))
+        .route("/files", get(get_files))
+        .route("/jobs", get(get_jobs))
+        .route("/jobs/:id", get(get_job))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::default().include_headers(true)),
         )
-        .with_state(file_search_index);
+        .with_state(AppState {
+            index,
+            jobs,
+            max_result_window: MaxResultWindow(max_result_window),
+        });
     axum::Server::bind(&addr)
         .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
     Ok(())
 }
+/// Parses a stemmer language by its english name, e.g. `french`.
+fn parse_language(name: &str) -> Result<Language, Box<dyn Error>> {
+    let mut chars = name.trim().chars();
+    let capitalized = match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    };
+    serde_json::from_value(serde_json::Value::String(capitalized))
+        .map_err(|_| format!("unsupported stemmer language {name}").into())
+}
+
+fn facet_options(fields: Vec<String>, size: Option<usize>) -> Result<FacetOptions, StatusCode> {
+    if let Some(field) = fields.iter().find(|f| !FACET_FIELDS.contains(&f.as_str())) {
+        tracing::error!("{field} is not a facet field");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let size = size.unwrap_or(DEFAULT_FACET_SIZE);
+    if size > MAX_FACET_SIZE {
+        tracing::error!("facet size {size} is too large");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(FacetOptions { fields, size })
+}
+
+fn group_options(
+    group_by: Option<GroupBy>,
+    hits_per_group: Option<usize>,
+) -> Result<Option<GroupOptions>, StatusCode> {
+    let hits_per_group = hits_per_group.unwrap_or(DEFAULT_HITS_PER_GROUP);
+    if hits_per_group > MAX_HITS_PER_GROUP {
+        tracing::error!("{hits_per_group} hits per group is too many");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(group_by.map(|by| GroupOptions { by, hits_per_group }))
+}
+
+/// Empty parameters, e.g. `&sheet=`, are not filters.
+fn filter(value: &Option<String>) -> Option<String> {
+    value.clone().filter(|v| !v.is_empty())
+}
+
+fn search_filters(
+    file_name: &Option<String>,
+    sheet: &Option<String>,
+    header: &Option<String>,
+    file_type: &Option<String>,
+    language: &Option<String>,
+) -> Result<SearchFilters, StatusCode> {
+    Ok(SearchFilters {
+        file_name: filter(file_name),
+        sheet: filter(sheet),
+        header: filter(header),
+        file_type: filter(file_type),
+        language: parse_doc_language(&filter(language))?,
+    })
+}
+
+fn fuzzy_options(
+    distance: Option<u8>,
+    transpositions: Option<bool>,
+    prefix: bool,
+) -> Result<FuzzyOptions, StatusCode> {
+    let default_fuzzy = FuzzyOptions::default();
+    let fuzzy = FuzzyOptions {
+        distance: distance.unwrap_or(default_fuzzy.distance),
+        transpositions: transpositions.unwrap_or(default_fuzzy.transpositions),
+        prefix,
+    };
+    if fuzzy.distance > MAX_FUZZY_DISTANCE {
+        tracing::error!("fuzzy distance {} is too large", fuzzy.distance);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(fuzzy)
+}
+
+fn sort_options(
+    sort: Option<&str>,
+    order: Option<SortOrder>,
+    group: Option<GroupOptions>,
+) -> Result<SortOptions, StatusCode> {
+    let by = match sort {
+        Some(sort) => sort.parse::<SortBy>().map_err(|e| {
+            tracing::error!("{e}");
+            StatusCode::BAD_REQUEST
+        })?,
+        None => SortBy::Relevance,
+    };
+    if group.is_some() && by != SortBy::Relevance {
+        tracing::error!("grouped results are sorted by relevance");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let order = order.unwrap_or_else(|| by.default_order());
+    Ok(SortOptions { by, order })
+}
+
+/// The hits of a page must be within the window, the pages after a cursor too.
+fn check_result_window(
+    page: usize,
+    per_page: usize,
+    search_after: bool,
+    max_result_window: usize,
+) -> Result<(), StatusCode> {
+    let window = match search_after {
+        true => Some(per_page),
+        false => page
+            .checked_add(1)
+            .and_then(|pages| pages.checked_mul(per_page)),
+    };
+    match window {
+        Some(window) if window <= max_result_window => Ok(()),
+        _ => {
+            tracing::error!(
+                "page {page} of {per_page} hits is beyond the result window of {max_result_window}"
+            );
+            Err(StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+fn search_after(
+    token: Option<&str>,
+    sort: &SortOptions,
+    group: Option<GroupOptions>,
+) -> Result<Option<SortCursor>, StatusCode> {
+    let Some(token) = token else {
+        return Ok(None);
+    };
+    if group.is_some() {
+        tracing::error!("grouped results cannot be paged with a cursor");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    SortCursor::decode(token, sort).map(Some).map_err(|e| {
+        tracing::error!("{e}");
+        StatusCode::BAD_REQUEST
+    })
+}
+
+fn parse_doc_language(code: &Option<String>) -> Result<Option<&'static DocLanguage>, StatusCode> {
+    code.as_deref()
+        .map(|code| {
+            DocLanguage::from_code(code).ok_or_else(|| {
+                tracing::error!("unsupported language {code}");
+                StatusCode::BAD_REQUEST
+            })
+        })
+        .transpose()
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|v| !v.is_empty())
+}
+
 pub fn setup_tracing() -> Result<(), Box<dyn Error>> {
     let offset_hours = {
         let now = Local::now();
@@ -106,82 +582,364 @@ pub fn setup_tracing() -> Result<(), Box<dyn Error>> {
 
 async fn get_search(
     query: Query<SearchRequest>,
-    State(fsi): State<FileSearchIndex>,
+    State(index): State<SharedIndex>,
+    State(MaxResultWindow(max_result_window)): State<MaxResultWindow>,
 ) -> axum::response::Result<impl IntoResponse> {
-    let docs = fsi
-        .search(query.page, query.per_page, &query.q, &query.query_type)
+    query.validate(max_result_window)?;
+    let group = group_options(query.group_by, query.hits_per_group)?;
+    let sort = sort_options(filter(&query.sort).as_deref(), query.order, group)?;
+    let options = SearchOptions {
+        filters: search_filters(
+            &query.file_name,
+            &query.sheet,
+            &query.header,
+            &query.file_type,
+            &query.language,
+        )?,
+        fuzzy: fuzzy_options(query.distance, query.transpositions, query.prefix)?,
+        snippet_max_chars: query
+            .highlight
+            .then(|| query.snippet_max_chars.unwrap_or(DEFAULT_SNIPPET_MAX_CHARS)),
+        facets: facet_options(
+            query
+                .facets
+                .as_deref()
+                .map(|facets| split_list(facets).map(String::from).collect())
+                .unwrap_or_default(),
+            query.facet_size,
+        )?,
+        group,
+        search_after: search_after(filter(&query.search_after).as_deref(), &sort, group)?,
+        sort,
+    };
+    let docs = index
+        .current()
+        .search(
+            query.page,
+            query.per_page,
+            &query.q,
+            &query.query_type,
+            &options,
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(docs))
+}
+
+/// Streams every hit, the response is written while the index is read.
+async fn get_search_export(
+    query: Query<ExportRequest>,
+    State(index): State<SharedIndex>,
+) -> axum::response::Result<impl IntoResponse> {
+    let options = SearchOptions {
+        filters: search_filters(
+            &query.file_name,
+            &query.sheet,
+            &query.header,
+            &query.file_type,
+            &query.language,
+        )?,
+        fuzzy: fuzzy_options(query.distance, query.transpositions, query.prefix)?,
+        ..Default::default()
+    };
+    let index = index.current();
+    let (search_query, highlighter) = index
+        .prepare_search(&query.q, &query.query_type, &options)
+        .map_err(|e| {
+            tracing::error!("invalid query: {e}");
+            StatusCode::BAD_REQUEST
+        })?;
+    let format = query.format;
+    let (writer, reader) = tokio::io::duplex(EXPORT_BUFFER_SIZE);
+    tokio::task::spawn_blocking(move || {
+        let out = SyncIoBridge::new(writer);
+        match export(&index, search_query.as_ref(), &highlighter, format, out) {
+            Ok(count) => tracing::info!("exported {count} hits"),
+            Err(e) => tracing::error!("export failed: {e}"),
+        }
+    });
+    let headers = [
+        (header::CONTENT_TYPE, format.content_type().to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"search-export.{}\"",
+                format.extension()
+            ),
+        ),
+    ];
+    Ok((headers, StreamBody::new(ReaderStream::new(reader))))
+}
+
+async fn post_search(
+    State(index): State<SharedIndex>,
+    State(MaxResultWindow(max_result_window)): State<MaxResultWindow>,
+    Json(request): Json<QuerySearchRequest>,
+) -> axum::response::Result<impl IntoResponse> {
+    request.validate(max_result_window)?;
+    let group = group_options(request.group_by, request.hits_per_group)?;
+    let sort = sort_options(request.sort.as_deref(), request.order, group)?;
+    let options = SearchOptions {
+        snippet_max_chars: request.highlight.then(|| {
+            request
+                .snippet_max_chars
+                .unwrap_or(DEFAULT_SNIPPET_MAX_CHARS)
+        }),
+        facets: facet_options(request.facets, request.facet_size)?,
+        group,
+        search_after: search_after(request.search_after.as_deref(), &sort, group)?,
+        sort,
+        ..Default::default()
+    };
+    let index = index.current();
+    let query = request.query.compile(&index).map_err(|e| {
+        tracing::error!("invalid query: {e}");
+        (StatusCode::BAD_REQUEST, e.to_string())
+    })?;
+    let highlighter = Highlighter::new(&index, index.cell_value_field, None, &query, None)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let docs = index
+        .search_query(
+            request.page,
+            request.per_page,
+            &query,
+            &highlighter,
+            &options,
+        )
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     Ok(Json(docs))
 }
 
+async fn get_suggest(
+    query: Query<SuggestRequest>,
+    State(index): State<SharedIndex>,
+) -> axum::response::Result<impl IntoResponse> {
+    if query.limit > MAX_SUGGESTIONS {
+        tracing::error!("suggestion limit {} is too large", query.limit);
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+    let options = SuggestOptions {
+        limit: query.limit,
+        headers: query.headers,
+        file_names: query.file_names,
+    };
+    let suggestions = index
+        .current()
+        .suggest(&query.prefix, &options)
+        .map_err(|e| {
+            tracing::error!("could not suggest completions: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(SuggestResponse { suggestions }))
+}
+
+async fn get_files(
+    query: Query<FilesRequest>,
+    State(index): State<SharedIndex>,
+    State(MaxResultWindow(max_result_window)): State<MaxResultWindow>,
+) -> axum::response::Result<impl IntoResponse> {
+    if query.per_page > max_result_window {
+        tracing::error!(
+            "{} files per page is beyond the result window of {max_result_window}",
+            query.per_page
+        );
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+    let (total, files) = index
+        .current()
+        .indexed_files(query.page, query.per_page)
+        .map_err(|e| {
+            tracing::error!("could not list indexed files: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(FilesResponse {
+        total,
+        page: query.page,
+        per_page: query.per_page,
+        files,
+    }))
+}
+
+async fn get_jobs(State(jobs): State<JobRegistry>) -> impl IntoResponse {
+    Json(jobs.list().await)
+}
+
+async fn get_job(
+    State(jobs): State<JobRegistry>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> axum::response::Result<impl IntoResponse> {
+    let job = jobs.get(&id).await.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(job))
+}
+
 async fn post_index(
-    State(fsi): State<FileSearchIndex>,
+    State(index): State<SharedIndex>,
+    State(jobs): State<JobRegistry>,
     axum::extract::Json(index_request): axum::extract::Json<IndexRequest>,
 ) -> axum::response::Result<impl IntoResponse> {
     let path = PathBuf::from(index_request.file_path);
     tracing::info!("getting path {path:?}");
-    index_path(path, fsi).await
+    if !path.exists() || !is_supported(&path) {
+        tracing::error!("{path:?} not yet supported");
+        return Err(ErrorResponse::from(StatusCode::FORBIDDEN));
+    }
+    let language = parse_doc_language(&index_request.language)?;
+    let fsi = write_access(&index)?;
+    let job_id = jobs
+        .spawn(
+            JobKind::Index,
+            path.to_string_lossy().to_string(),
+            async move {
+                let documents_added = index_path(path, fsi.clone(), language).await?;
+                Ok(JobReport {
+                    documents_added,
+                    ..Default::default()
+                })
+            },
+        )
+        .await;
+    Ok((StatusCode::ACCEPTED, Json(JobCreated { job_id })))
 }
 
-async fn reindex_from_directory(
-    State(fsi): State<FileSearchIndex>,
-    reindex: Query<ReindexRequest>,
+async fn delete_index(
+    State(index): State<SharedIndex>,
+    axum::extract::Json(delete_request): axum::extract::Json<DeleteRequest>,
 ) -> axum::response::Result<impl IntoResponse> {
-    let path = PathBuf::from(&reindex.directory_path);
-    if !path.exists() || !path.is_dir() {
-        tracing::error!("path {path:?} doesn't exist or is not a directory");
-        return Err(ErrorResponse::from(StatusCode::BAD_REQUEST));
+    // indexed paths are canonical, but the file may not exist anymore
+    let canonical = |path: String| {
+        Path::new(&path)
+            .canonicalize()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or(path)
+    };
+    let fsi = write_access(&index)?;
+    let query = match delete_request {
+        DeleteRequest {
+            file_path: Some(file_path),
+            directory_path: None,
+            file_name: None,
+        } => Ok(fsi.file_path_query(&canonical(file_path))),
+        DeleteRequest {
+            file_path: None,
+            directory_path: Some(directory_path),
+            file_name: None,
+        } => fsi.directory_query(&canonical(directory_path)),
+        DeleteRequest {
+            file_path: None,
+            directory_path: None,
+            file_name: Some(file_name),
+        } => fsi.file_name_query(&file_name),
+        _ => {
+            tracing::error!("exactly one of file_path, directory_path or file_name is expected");
+            return Err(ErrorResponse::from(StatusCode::BAD_REQUEST));
+        }
     }
-
-    let mut index_writer = fsi.index_writer.lock().await;
-
-    index_writer.delete_all_documents().map_err(|e| {
-        tracing::error!("error while deleting all docs: {e:?}");
-        StatusCode::INTERNAL_SERVER_ERROR
+    .map_err(|e| {
+        tracing::error!("invalid delete request: {e:?}");
+        StatusCode::BAD_REQUEST
     })?;
 
-    index_writer.commit().map_err(|e| {
-        tracing::error!("error while committing: {e:?}");
+    let documents_deleted = fsi.delete_documents(query).await.map_err(|e| {
+        tracing::error!("could not delete documents: {e:?}");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    Ok(Json(DeleteResponse { documents_deleted }))
+}
 
-    for file in path.read_dir().map_err(|e| {
-        tracing::error!("could not read dir {e:?}");
-        ErrorResponse::from(StatusCode::INTERNAL_SERVER_ERROR)
-    })? {
-        let file = file.map_err(|e| {
-            tracing::error!("could not read file {e:?}");
-            ErrorResponse::from(StatusCode::INTERNAL_SERVER_ERROR)
-        })?;
-        index_path(file.path(), fsi.clone()).await?;
-    }
+async fn reindex_from_directory(
+    State(index): State<SharedIndex>,
+    State(jobs): State<JobRegistry>,
+    reindex: axum_extra::extract::Query<ReindexRequest>,
+) -> axum::response::Result<impl IntoResponse> {
+    let (path, walk_options) = reindex.validate()?;
+
+    let job_id = jobs
+        .spawn(
+            JobKind::Reindex,
+            path.to_string_lossy().to_string(),
+            async move {
+                let walk =
+                    tokio::task::spawn_blocking(move || walk_directory(&path, &walk_options))
+                        .await??;
+
+                index
+                    .rebuild(|shadow| async move {
+                        let mut report = JobReport {
+                            skipped: walk.skipped,
+                            ..Default::default()
+                        };
+                        for file in walk.files {
+                            if !is_supported(&file) {
+                                report
+                                    .skipped
+                                    .push(SkippedEntry::new(&file, "unsupported file type"));
+                                continue;
+                            }
+                            match index_path(file.clone(), shadow.clone(), None).await {
+                                Ok(documents_added) => report.documents_added += documents_added,
+                                // the shadow index can't be written to, abort the rebuild
+                                Err(e) if e.is::<tantivy::TantivyError>() => return Err(e),
+                                Err(e) => {
+                                    tracing::error!("could not index {file:?}: {e:?}");
+                                    report
+                                        .skipped
+                                        .push(SkippedEntry::new(&file, format!("failed: {e}")));
+                                }
+                            }
+                        }
+                        Ok(report)
+                    })
+                    .await
+            },
+        )
+        .await;
+
+    Ok((StatusCode::ACCEPTED, Json(JobCreated { job_id })))
+}
+
+async fn sync_from_directory(
+    State(index): State<SharedIndex>,
+    State(jobs): State<JobRegistry>,
+    sync: axum_extra::extract::Query<SyncRequest>,
+) -> axum::response::Result<impl IntoResponse> {
+    let (path, walk_options) = sync.validate()?;
+    let fsi = write_access(&index)?;
+
+    let job_id = jobs
+        .spawn(
+            JobKind::Sync,
+            path.to_string_lossy().to_string(),
+            async move { sync::sync_directory(path, walk_options, fsi.clone()).await },
+        )
+        .await;
 
-    Ok(StatusCode::ACCEPTED)
+    Ok((StatusCode::ACCEPTED, Json(JobCreated { job_id })))
 }
 
+/// Writes are rejected while the index is rebuilt, they would be lost once it is swapped.
+fn write_access(index: &SharedIndex) -> Result<IndexWriteGuard, StatusCode> {
+    index.try_write().ok_or_else(|| {
+        tracing::error!("the index is being rebuilt, try again later");
+        StatusCode::CONFLICT
+    })
+}
+
+fn is_supported(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("xls" | "xlsx" | "csv" | "pdf")
+    )
+}
+
+/// Without `language`, the language of each sheet, csv or pdf passage is detected.
 async fn index_path(
     path: PathBuf,
     fsi: FileSearchIndex,
-) -> axum::response::Result<impl IntoResponse> {
-    match path.extension().and_then(|e| e.to_str()).ok_or_else(|| {
-        tracing::error!("could not determine extension");
-        StatusCode::INTERNAL_SERVER_ERROR
-    })? {
-        "xls" | "xlsx" if path.exists() => {
-            tokio::spawn(async move { index_xlsx::index_xlsx_file(fsi, path).await });
-            Ok(StatusCode::ACCEPTED)
-        }
-        "csv" if path.exists() => {
-            tokio::spawn(async move { index_csv::index_csv_file(fsi, path).await });
-            Ok(StatusCode::ACCEPTED)
-        }
-        "pdf" if path.exists() => {
-            tokio::spawn(async move { index_pdf::index_pdf_file(fsi, path).await });
-            Ok(StatusCode::ACCEPTED)
-        }
-        _ => {
-            tracing::error!("{path:?} not yet supported");
-            Err(ErrorResponse::from(StatusCode::FORBIDDEN))
-        }
+    language: Option<&'static DocLanguage>,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("xls" | "xlsx") => index_xlsx::index_xlsx_file(fsi, path, language).await,
+        Some("csv") => index_csv::index_csv_file(fsi, path, language).await,
+        Some("pdf") => index_pdf::index_pdf_file(fsi, path, language).await,
+        _ => Err(format!("{path:?} not yet supported").into()),
     }
 }
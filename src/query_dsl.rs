@@ -0,0 +1,653 @@
+use std::{error::Error, ops::Bound};
+
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use tantivy::{
+    query::{
+        AllQuery, BooleanQuery, BoostQuery, ConstScoreQuery, Occur, PhraseQuery, Query, RangeQuery,
+        RegexQuery, TermQuery,
+    },
+    schema::{Field, FieldType, IndexRecordOption, Type},
+    DateTime, Score, Term,
+};
+
+use crate::{
+    index_tantivy::{terms_union, FileSearchIndex, FuzzyOptions, MAX_FUZZY_DISTANCE, ROW_FIELD},
+    utils::{fold, fold_pattern, parse_date, parse_number, parse_period},
+};
+
+/// A query tree, e.g.
+/// `{"bool": {"must": [{"term": {"value": "acme"}}], "filter": [{"term": {"field": "sheet_name", "value": "2024"}}]}}`.
+///
+/// `field` defaults to `cell_value`, `row.<header>` targets the cells of a column. Values
+/// targeting cells are normalized like the indexed text, e.g. `Société` matches `societe`.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryNode {
+    Bool {
+        #[serde(default)]
+        must: Vec<QueryNode>,
+        #[serde(default)]
+        should: Vec<QueryNode>,
+        #[serde(default)]
+        must_not: Vec<QueryNode>,
+        /// Must match, without contributing to the score.
+        #[serde(default)]
+        filter: Vec<QueryNode>,
+        boost: Option<Score>,
+    },
+    Term {
+        field: Option<String>,
+        value: Value,
+        boost: Option<Score>,
+    },
+    Phrase {
+        field: Option<String>,
+        value: String,
+        #[serde(default)]
+        slop: u32,
+        boost: Option<Score>,
+    },
+    Prefix {
+        field: Option<String>,
+        value: String,
+        boost: Option<Score>,
+    },
+    Regex {
+        field: Option<String>,
+        pattern: String,
+        boost: Option<Score>,
+    },
+    Fuzzy {
+        field: Option<String>,
+        value: String,
+        #[serde(default = "default_distance")]
+        distance: u8,
+        #[serde(default = "default_transpositions")]
+        transpositions: bool,
+        /// Matches the terms starting with a fuzzy match of `value`.
+        #[serde(default)]
+        prefix: bool,
+        boost: Option<Score>,
+    },
+    Range {
+        field: Option<String>,
+        #[serde(flatten)]
+        bounds: RangeBounds,
+        boost: Option<Score>,
+    },
+    All {
+        boost: Option<Score>,
+    },
+}
+
+fn default_distance() -> u8 {
+    2
+}
+
+fn default_transpositions() -> bool {
+    true
+}
+
+/// What a query node targets: a field of the schema or a column of spreadsheet rows.
+enum Target {
+    Field(Field, FieldType),
+    Column(String),
+}
+
+impl Target {
+    fn resolve(fsi: &FileSearchIndex, field: &Option<String>) -> Result<Target, Box<dyn Error>> {
+        let Some(name) = field else {
+            let field = fsi.cell_value_field;
+            return Ok(Target::Field(field, field_type(fsi, field)));
+        };
+        if let Some(header) = name.strip_prefix(&format!("{ROW_FIELD}.")) {
+            return Ok(Target::Column(header.to_string()));
+        }
+        let field = fsi
+            .schema
+            .get_field(name)
+            .map_err(|_| format!("unknown field {name}"))?;
+        Ok(Target::Field(field, field_type(fsi, field)))
+    }
+}
+
+fn field_type(fsi: &FileSearchIndex, field: Field) -> FieldType {
+    fsi.schema.get_field_entry(field).field_type().clone()
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn to_u64(value: &Value) -> Result<u64, Box<dyn Error>> {
+    match value {
+        Value::Number(n) => n.as_u64().ok_or_else(|| format!("{n} is not a u64").into()),
+        v => Ok(text(v).parse()?),
+    }
+}
+
+fn to_date(value: &Value) -> Result<DateTime, Box<dyn Error>> {
+    parse_date(&text(value)).ok_or_else(|| format!("{value} is not a date").into())
+}
+
+fn bound<T>(
+    exclusive: &Option<Value>,
+    inclusive: &Option<Value>,
+    convert: impl Fn(&Value) -> Result<T, Box<dyn Error>>,
+) -> Result<Bound<T>, Box<dyn Error>> {
+    Ok(match (exclusive, inclusive) {
+        (Some(v), _) => Bound::Excluded(convert(v)?),
+        (None, Some(v)) => Bound::Included(convert(v)?),
+        (None, None) => Bound::Unbounded,
+    })
+}
+
+fn to_f64(value: &Value) -> Result<f64, Box<dyn Error>> {
+    match value {
+        Value::Number(n) => n.as_f64().ok_or_else(|| format!("{n} is not a f64").into()),
+        v => parse_number(&text(v)).ok_or_else(|| format!("{v} is not a number").into()),
+    }
+}
+
+fn to_period(value: &Value) -> Result<(DateTime, DateTime), Box<dyn Error>> {
+    parse_period(&text(value)).ok_or_else(|| format!("{value} is not a date").into())
+}
+
+/// Bounds of a range query. Dates can be given as periods, e.g. `2023`, `2023-Q4`,
+/// `2023-10` or `2023-10-05`: `gte` starts at the beginning of the period, `lte` ends
+/// at its end and `in` matches the whole period.
+#[derive(Deserialize)]
+pub struct RangeBounds {
+    gt: Option<Value>,
+    gte: Option<Value>,
+    lt: Option<Value>,
+    lte: Option<Value>,
+    #[serde(rename = "in")]
+    within: Option<Value>,
+}
+
+impl RangeBounds {
+    fn values(&self) -> impl Iterator<Item = &Value> {
+        [&self.gt, &self.gte, &self.lt, &self.lte, &self.within]
+            .into_iter()
+            .flatten()
+    }
+
+    /// Type of the values of the column `header` the bounds apply to: numbers, dates or
+    /// text. Bounds that are both numbers and periods, e.g. `2024`, are dates when the
+    /// column holds dates and no numbers.
+    fn value_type(&self, fsi: &FileSearchIndex, header: &str) -> Result<Type, Box<dyn Error>> {
+        let numbers = self.within.is_none() && self.values().all(|v| to_f64(v).is_ok());
+        let dates = self.values().all(|v| to_period(v).is_ok());
+        if numbers && dates {
+            let date_column = fsi.column_has_type(header, Type::Date)?
+                && !fsi.column_has_type(header, Type::F64)?;
+            Ok(if date_column { Type::Date } else { Type::F64 })
+        } else if numbers {
+            Ok(Type::F64)
+        } else if dates {
+            Ok(Type::Date)
+        } else if self.within.is_some() {
+            Err("`in` expects a date or a period".into())
+        } else {
+            Ok(Type::Str)
+        }
+    }
+
+    fn numbers(&self) -> Result<(Bound<f64>, Bound<f64>), Box<dyn Error>> {
+        Ok((
+            bound(&self.gt, &self.gte, to_f64)?,
+            bound(&self.lt, &self.lte, to_f64)?,
+        ))
+    }
+
+    fn dates(&self) -> Result<(Bound<DateTime>, Bound<DateTime>), Box<dyn Error>> {
+        if let Some(within) = &self.within {
+            let (start, end) = to_period(within)?;
+            return Ok((Bound::Included(start), Bound::Excluded(end)));
+        }
+        let lower = match (&self.gt, &self.gte) {
+            (Some(v), _) => Bound::Included(to_period(v)?.1),
+            (None, Some(v)) => Bound::Included(to_period(v)?.0),
+            (None, None) => Bound::Unbounded,
+        };
+        let upper = match (&self.lt, &self.lte) {
+            (Some(v), _) => Bound::Excluded(to_period(v)?.0),
+            (None, Some(v)) => Bound::Excluded(to_period(v)?.1),
+            (None, None) => Bound::Unbounded,
+        };
+        Ok((lower, upper))
+    }
+
+    fn texts(&self) -> Result<(Bound<String>, Bound<String>), Box<dyn Error>> {
+        Ok((
+            bound(&self.gt, &self.gte, |v| Ok(text(v)))?,
+            bound(&self.lt, &self.lte, |v| Ok(text(v)))?,
+        ))
+    }
+}
+
+fn in_bounds(text: &str, lower: &Bound<String>, upper: &Bound<String>) -> bool {
+    let above = match lower {
+        Bound::Included(l) => text >= l.as_str(),
+        Bound::Excluded(l) => text > l.as_str(),
+        Bound::Unbounded => true,
+    };
+    let below = match upper {
+        Bound::Included(u) => text <= u.as_str(),
+        Bound::Excluded(u) => text < u.as_str(),
+        Bound::Unbounded => true,
+    };
+    above && below
+}
+
+impl QueryNode {
+    fn boost(&self) -> Option<Score> {
+        match self {
+            QueryNode::Bool { boost, .. }
+            | QueryNode::Term { boost, .. }
+            | QueryNode::Phrase { boost, .. }
+            | QueryNode::Prefix { boost, .. }
+            | QueryNode::Regex { boost, .. }
+            | QueryNode::Fuzzy { boost, .. }
+            | QueryNode::Range { boost, .. }
+            | QueryNode::All { boost } => *boost,
+        }
+    }
+
+    /// Compiles the tree to a tantivy query.
+    pub fn compile(&self, fsi: &FileSearchIndex) -> Result<Box<dyn Query>, Box<dyn Error>> {
+        let query = self.compile_node(fsi)?;
+        Ok(match self.boost() {
+            Some(boost) => Box::new(BoostQuery::new(query, boost)),
+            None => query,
+        })
+    }
+
+    fn compile_node(&self, fsi: &FileSearchIndex) -> Result<Box<dyn Query>, Box<dyn Error>> {
+        let query: Box<dyn Query> = match self {
+            QueryNode::Bool {
+                must,
+                should,
+                must_not,
+                filter,
+                ..
+            } => {
+                let mut clauses = vec![];
+                for node in must {
+                    clauses.push((Occur::Must, node.compile(fsi)?));
+                }
+                for node in should {
+                    clauses.push((Occur::Should, node.compile(fsi)?));
+                }
+                for node in filter {
+                    let query = Box::new(ConstScoreQuery::new(node.compile(fsi)?, 0.0));
+                    clauses.push((Occur::Must, query as Box<dyn Query>));
+                }
+                if clauses.is_empty() {
+                    // a query made of exclusions only matches nothing in tantivy
+                    clauses.push((Occur::Must, Box::new(AllQuery)));
+                }
+                for node in must_not {
+                    clauses.push((Occur::MustNot, node.compile(fsi)?));
+                }
+                Box::new(BooleanQuery::new(clauses))
+            }
+            QueryNode::Term { field, value, .. } => {
+                let term = match Target::resolve(fsi, field)? {
+                    Target::Column(header) if value.is_number() => {
+                        fsi.column_number_term(&header, to_f64(value)?)
+                    }
+                    Target::Column(header) => fsi
+                        .column_term(&header, &fsi.normalize(fsi.cell_value_field, &text(value))?),
+                    Target::Field(field, FieldType::Str(_)) if fsi.is_cell_value_field(field) => {
+                        Term::from_field_text(field, &fsi.normalize(field, &text(value))?)
+                    }
+                    Target::Field(field, FieldType::F64(_)) => {
+                        Term::from_field_f64(field, to_f64(value)?)
+                    }
+                    Target::Field(field, FieldType::Str(_)) => {
+                        Term::from_field_text(field, &text(value))
+                    }
+                    Target::Field(field, FieldType::U64(_)) => {
+                        Term::from_field_u64(field, to_u64(value)?)
+                    }
+                    Target::Field(field, FieldType::Date(_)) => {
+                        Term::from_field_date(field, to_date(value)?)
+                    }
+                    Target::Field(..) => return Err("unsupported field for a term query".into()),
+                };
+                Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs))
+            }
+            QueryNode::Phrase {
+                field, value, slop, ..
+            } => {
+                let target = Target::resolve(fsi, field)?;
+                let tokenizer_field = match &target {
+                    Target::Column(_) => fsi.row_field,
+                    Target::Field(field, _) => *field,
+                };
+                let mut tokenizer = fsi.index.tokenizer_for_field(tokenizer_field)?;
+                let mut token_stream = tokenizer.token_stream(value);
+                let mut terms = vec![];
+                while let Some(token) = token_stream.next() {
+                    let term = match &target {
+                        Target::Column(header) => fsi.column_term(header, &token.text),
+                        Target::Field(field, _) => Term::from_field_text(*field, &token.text),
+                    };
+                    terms.push((token.position, term));
+                }
+                match terms.len() {
+                    0 => Box::new(BooleanQuery::union(vec![])),
+                    1 => Box::new(TermQuery::new(
+                        terms.remove(0).1,
+                        IndexRecordOption::WithFreqs,
+                    )),
+                    _ => Box::new(PhraseQuery::new_with_offset_and_slop(terms, *slop)),
+                }
+            }
+            QueryNode::Prefix { field, value, .. } => match Target::resolve(fsi, field)? {
+                Target::Column(header) => {
+                    let value = fold(value);
+                    Box::new(terms_union(fsi.expand_column_terms(&header, |text| {
+                        text.starts_with(value.as_str())
+                    })?))
+                }
+                Target::Field(field, _) => {
+                    let value = match fsi.is_cell_value_field(field) {
+                        true => fold(value),
+                        false => value.clone(),
+                    };
+                    Box::new(RegexQuery::from_pattern(
+                        &format!("{}.*", regex::escape(&value)),
+                        field,
+                    )?)
+                }
+            },
+            QueryNode::Regex { field, pattern, .. } => match Target::resolve(fsi, field)? {
+                Target::Column(header) => {
+                    let regex = Regex::new(&format!("^(?:{})$", fold_pattern(pattern)))?;
+                    Box::new(terms_union(
+                        fsi.expand_column_terms(&header, |text| regex.is_match(text))?,
+                    ))
+                }
+                Target::Field(field, _) if fsi.is_cell_value_field(field) => {
+                    Box::new(RegexQuery::from_pattern(&fold_pattern(pattern), field)?)
+                }
+                Target::Field(field, _) => Box::new(RegexQuery::from_pattern(pattern, field)?),
+            },
+            QueryNode::Fuzzy {
+                field,
+                value,
+                distance,
+                transpositions,
+                prefix,
+                ..
+            } => {
+                if *distance > MAX_FUZZY_DISTANCE {
+                    return Err(format!("distance must be at most {MAX_FUZZY_DISTANCE}").into());
+                }
+                let options = FuzzyOptions {
+                    distance: *distance,
+                    transpositions: *transpositions,
+                    prefix: *prefix,
+                };
+                match Target::resolve(fsi, field)? {
+                    Target::Column(header) => {
+                        let value = fsi.normalize(fsi.cell_value_field, value)?;
+                        Box::new(terms_union(fsi.expand_column_terms(&header, |text| {
+                            options.matches(&value, text)
+                        })?))
+                    }
+                    Target::Field(field, _) if fsi.is_cell_value_field(field) => {
+                        options.query(Term::from_field_text(field, &fsi.normalize(field, value)?))
+                    }
+                    Target::Field(field, _) => options.query(Term::from_field_text(field, value)),
+                }
+            }
+            QueryNode::Range { field, bounds, .. } => match Target::resolve(fsi, field)? {
+                Target::Column(header) => match bounds.value_type(fsi, &header)? {
+                    Type::F64 => {
+                        let (lower, upper) = bounds.numbers()?;
+                        Box::new(fsi.column_number_range(&header, lower, upper))
+                    }
+                    Type::Date => {
+                        let (lower, upper) = bounds.dates()?;
+                        Box::new(fsi.column_date_range(&header, lower, upper))
+                    }
+                    _ => {
+                        let (lower, upper) = bounds.texts()?;
+                        Box::new(terms_union(fsi.expand_column_terms(&header, |text| {
+                            in_bounds(text, &lower, &upper)
+                        })?))
+                    }
+                },
+                Target::Field(field, field_type) => {
+                    let name = fsi.schema.get_field_name(field).to_string();
+                    if bounds.within.is_some() && !matches!(field_type, FieldType::Date(_)) {
+                        return Err(format!("`in` expects a date field, `{name}` isn't one").into());
+                    }
+                    match field_type {
+                        FieldType::U64(_) => Box::new(RangeQuery::new_u64_bounds(
+                            name,
+                            bound(&bounds.gt, &bounds.gte, to_u64)?,
+                            bound(&bounds.lt, &bounds.lte, to_u64)?,
+                        )),
+                        FieldType::F64(_) => {
+                            let (lower, upper) = bounds.numbers()?;
+                            Box::new(RangeQuery::new_f64_bounds(name, lower, upper))
+                        }
+                        FieldType::Date(_) => {
+                            let (lower, upper) = bounds.dates()?;
+                            Box::new(RangeQuery::new_date_bounds(name, lower, upper))
+                        }
+                        FieldType::Str(_) => {
+                            let (lower, upper) = bounds.texts()?;
+                            Box::new(RangeQuery::new_str_bounds(
+                                name,
+                                lower.as_ref().map(String::as_str),
+                                upper.as_ref().map(String::as_str),
+                            ))
+                        }
+                        _ => return Err("unsupported field for a range query".into()),
+                    }
+                }
+            },
+            QueryNode::All { .. } => Box::new(AllQuery),
+        };
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::{
+        highlight::Highlighter,
+        index_csv::index_csv_file,
+        index_tantivy::{QueryType, SearchFilters, SearchOptions},
+        test_utils::test_index,
+    };
+
+    use super::QueryNode;
+
+    #[tokio::test]
+    async fn test_compile() {
+        let fsi = test_index();
+        let dir = &fsi.dir;
+        for (file_name, content) in [
+            (
+                "invoices.csv",
+                "Supplier,Note\nacme,late delivery of parts\nglobex,parts delivered late\ninitech,on time\n",
+            ),
+            ("suppliers.csv", "Supplier\nacme\n"),
+        ] {
+            std::fs::write(dir.join(file_name), content).unwrap();
+            index_csv_file(fsi.clone(), dir.join(file_name), None)
+                .await
+                .unwrap();
+        }
+        fsi.index_reader.reload().unwrap();
+
+        let total_hits = |query: serde_json::Value| {
+            let query: QueryNode = serde_json::from_value(query).unwrap();
+            let query = query.compile(&fsi).unwrap();
+            let highlighter =
+                Highlighter::new(&fsi, fsi.cell_value_field, None, &query, None).unwrap();
+            fsi.search_query(0, 10, &query, &highlighter, &Default::default())
+                .unwrap()
+                .total_hits
+        };
+        assert_eq!(2, total_hits(json!({"term": {"value": "acme"}})));
+        assert_eq!(
+            1,
+            total_hits(json!({"bool": {
+                "must": [{"term": {"field": "row.Supplier", "value": "acme"}}],
+                "filter": [{"regex": {"field": "file_name", "pattern": "inv.*"}}]
+            }}))
+        );
+        assert_eq!(
+            1,
+            total_hits(json!({"phrase": {"field": "row.Note", "value": "late delivery"}}))
+        );
+        assert_eq!(0, total_hits(json!({"phrase": {"value": "late parts"}})));
+        assert_eq!(
+            1,
+            total_hits(json!({"phrase": {"value": "late parts", "slop": 2}}))
+        );
+        assert_eq!(
+            2,
+            total_hits(json!({"prefix": {"field": "row.Note", "value": "deliver"}}))
+        );
+        assert_eq!(
+            2,
+            total_hits(
+                json!({"fuzzy": {"field": "row.Supplier", "value": "acmee", "distance": 1}})
+            )
+        );
+        assert_eq!(
+            1,
+            total_hits(json!({"range": {"field": "row.Supplier", "gt": "acme", "lt": "h"}}))
+        );
+        assert_eq!(
+            2,
+            total_hits(json!({"range": {"field": "row_number", "gte": 3}}))
+        );
+        assert_eq!(
+            2,
+            total_hits(json!({"bool": {"must_not": [{"term": {"value": "late"}}]}}))
+        );
+        assert!(serde_json::from_value::<QueryNode>(
+            json!({"term": {"field": "nope", "value": "x"}})
+        )
+        .unwrap()
+        .compile(&fsi)
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_typed_ranges() {
+        let fsi = test_index();
+        let dir = &fsi.dir;
+        let csv_path = dir.join("invoices.csv");
+        std::fs::write(
+            &csv_path,
+            "Customer,Amount,Date\nacme,1500.5,2023-11-15\nglobex,800,2023-09-30\ninitech,4999,2024-01-02\n",
+        )
+        .unwrap();
+        index_csv_file(fsi.clone(), &csv_path, None).await.unwrap();
+        fsi.index_reader.reload().unwrap();
+
+        let search = |query: serde_json::Value| {
+            let query: QueryNode = serde_json::from_value(query).unwrap();
+            let query = query.compile(&fsi).unwrap();
+            let highlighter =
+                Highlighter::new(&fsi, fsi.cell_value_field, None, &query, None).unwrap();
+            fsi.search_query(0, 10, &query, &highlighter, &Default::default())
+                .unwrap()
+        };
+        let total_hits = |query| search(query).total_hits;
+        assert_eq!(
+            2,
+            total_hits(json!({"range": {"field": "row.Amount", "gte": 1000, "lte": 5000}}))
+        );
+        assert_eq!(
+            1,
+            total_hits(json!({"range": {"field": "row.Date", "in": "2023-Q4"}}))
+        );
+        assert_eq!(
+            2,
+            total_hits(json!({"range": {"field": "row.Date", "gte": "2023-Q4"}}))
+        );
+        assert_eq!(
+            1,
+            total_hits(json!({"range": {"field": "row.Date", "lte": "2023-Q3"}}))
+        );
+        assert_eq!(
+            1,
+            total_hits(json!({"range": {"field": "row.Date", "gte": "2024"}}))
+        );
+        assert_eq!(
+            2,
+            total_hits(json!({"range": {"field": "row.Date", "lte": "2023"}}))
+        );
+        assert_eq!(
+            2,
+            total_hits(json!({"range": {"field": "row.Date", "lt": 2024}}))
+        );
+        assert_eq!(
+            3,
+            total_hits(json!({"range": {"field": "row.Amount", "gte": "800"}}))
+        );
+        assert_eq!(
+            0,
+            total_hits(json!({"range": {"field": "row.Customer", "in": "2023"}}))
+        );
+        assert_eq!(
+            2,
+            total_hits(json!({"range": {"field": "cell_number", "gt": 1000}}))
+        );
+        assert_eq!(
+            2,
+            total_hits(json!({"range": {"field": "cell_date", "in": "2023"}}))
+        );
+        assert_eq!(
+            1,
+            total_hits(json!({"term": {"field": "row.Amount", "value": 800}}))
+        );
+        for field in ["file_size", "cell_number", "file_name"] {
+            let query: QueryNode =
+                serde_json::from_value(json!({"range": {"field": field, "in": "2023"}})).unwrap();
+            assert!(query.compile(&fsi).is_err());
+        }
+
+        let results = search(json!({"term": {"field": "row.Customer", "value": "acme"}}));
+        assert_eq!(
+            Some(json!({"Customer": "acme", "Amount": "1500.5", "Date": "2023-11-15"})),
+            results.hits[0].row.clone().map(serde_json::Value::Object)
+        );
+        let filters = SearchFilters {
+            header: Some("Amount".to_string()),
+            ..Default::default()
+        };
+        let results = fsi
+            .search(
+                0,
+                10,
+                "800",
+                &QueryType::TermQuery,
+                &SearchOptions {
+                    filters,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(1, results.total_hits);
+    }
+}
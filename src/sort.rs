@@ -0,0 +1,464 @@
+use std::{cmp::Ordering, collections::HashMap, io, str::FromStr};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use tantivy::{
+    collector::{Collector, SegmentCollector},
+    columnar::{Column, StrColumn},
+    DateTime, DocAddress, DocId, Score, SegmentOrdinal, SegmentReader,
+};
+
+use crate::{
+    index_tantivy::{
+        column_json_path, FILE_NAME_FIELD, FILE_PATH_FIELD, MODIFIED_AT_FIELD, ROW_NUMBER_FIELD,
+        ROW_VALUES_FIELD, SHEET_NAME_FIELD,
+    },
+    utils::{keep_first, parse_date, push_bounded},
+};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// What the hits of a search are sorted by.
+#[derive(Clone, PartialEq, Debug)]
+pub enum SortBy {
+    Relevance,
+    FileName,
+    ModifiedAt,
+    /// Rows in order, sheet by sheet and file by file.
+    RowNumber,
+    /// Numbers or dates of the cells of the column, header given.
+    Column(String),
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    /// `relevance`, `file_name`, `modified_at`, `row_number` or `column:<header>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relevance" => Ok(SortBy::Relevance),
+            "file_name" => Ok(SortBy::FileName),
+            "modified_at" => Ok(SortBy::ModifiedAt),
+            "row_number" => Ok(SortBy::RowNumber),
+            _ => match s.split_once(':') {
+                Some(("column", header)) if !header.is_empty() => {
+                    Ok(SortBy::Column(header.to_string()))
+                }
+                _ => Err(format!("cannot sort by {s}")),
+            },
+        }
+    }
+}
+
+impl SortBy {
+    /// Number of values a hit is sorted by.
+    fn len(&self) -> usize {
+        match self {
+            SortBy::RowNumber => 3,
+            _ => 1,
+        }
+    }
+
+    /// The best hits come first, the other sorts are ascending.
+    pub fn default_order(&self) -> SortOrder {
+        match self {
+            SortBy::Relevance => SortOrder::Desc,
+            _ => SortOrder::Asc,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SortOptions {
+    pub by: SortBy,
+    pub order: SortOrder,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        SortOptions {
+            by: SortBy::Relevance,
+            order: SortOrder::Desc,
+        }
+    }
+}
+
+/// Value a hit is sorted by, a hit has one per sort column.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(untagged)]
+pub enum SortValue {
+    Text(String),
+    Number(f64),
+}
+
+impl SortValue {
+    fn cmp(&self, other: &SortValue) -> Ordering {
+        match (self, other) {
+            (SortValue::Text(a), SortValue::Text(b)) => a.cmp(b),
+            (SortValue::Number(a), SortValue::Number(b)) => a.total_cmp(b),
+            (SortValue::Text(_), SortValue::Number(_)) => Ordering::Greater,
+            (SortValue::Number(_), SortValue::Text(_)) => Ordering::Less,
+        }
+    }
+}
+
+pub struct SortedDoc {
+    /// `None` for a hit without value, e.g. an empty cell.
+    pub values: Vec<Option<SortValue>>,
+    pub score: Score,
+    pub doc_address: DocAddress,
+}
+
+/// Position of a hit in the sort order, the next page starts after it. Document
+/// addresses change when segments are merged, pages may then skip or repeat a tie.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SortCursor {
+    pub values: Vec<Option<SortValue>>,
+    pub segment_ord: SegmentOrdinal,
+    pub doc_id: DocId,
+}
+
+impl SortCursor {
+    pub fn new(values: Vec<Option<SortValue>>, doc_address: DocAddress) -> SortCursor {
+        SortCursor {
+            values,
+            segment_ord: doc_address.segment_ord,
+            doc_id: doc_address.doc_id,
+        }
+    }
+
+    /// Opaque token of the cursor.
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    /// Cursor of a token, which must come from a search with the same sort.
+    pub fn decode(token: &str, options: &SortOptions) -> Result<SortCursor, String> {
+        let json = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|e| format!("invalid cursor: {e}"))?;
+        let cursor: SortCursor =
+            serde_json::from_slice(&json).map_err(|e| format!("invalid cursor: {e}"))?;
+        if cursor.values.len() != options.by.len() {
+            return Err("the cursor comes from another sort".to_string());
+        }
+        Ok(cursor)
+    }
+
+    fn doc_address(&self) -> DocAddress {
+        DocAddress::new(self.segment_ord, self.doc_id)
+    }
+}
+
+/// Collects the first `limit` hits in the sort order, after the cursor if any. Hits
+/// without a value come last, ties are broken by document address so the pages are
+/// stable.
+pub struct SortCollector {
+    pub options: SortOptions,
+    pub limit: usize,
+    pub after: Option<SortCursor>,
+}
+
+impl Collector for SortCollector {
+    type Fruit = Vec<SortedDoc>;
+    type Child = SortSegmentCollector;
+
+    fn for_segment(
+        &self,
+        segment_ord: SegmentOrdinal,
+        reader: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let fast_fields = reader.fast_fields();
+        let columns = match &self.options.by {
+            SortBy::Relevance => vec![SortColumn::Score],
+            SortBy::FileName => vec![SortColumn::Text(fast_fields.str(FILE_NAME_FIELD)?)],
+            SortBy::ModifiedAt => {
+                vec![SortColumn::Date(fast_fields.column_opt(MODIFIED_AT_FIELD)?)]
+            }
+            SortBy::RowNumber => vec![
+                SortColumn::Text(fast_fields.str(FILE_PATH_FIELD)?),
+                SortColumn::Text(fast_fields.str(SHEET_NAME_FIELD)?),
+                SortColumn::Number(fast_fields.column_opt(ROW_NUMBER_FIELD)?),
+            ],
+            SortBy::Column(header) => {
+                let name = format!("{ROW_VALUES_FIELD}.{}", column_json_path(header));
+                vec![SortColumn::Cell {
+                    numbers: fast_fields.column_opt(&name)?,
+                    texts: fast_fields.str(&name)?,
+                    dates: HashMap::new(),
+                }]
+            }
+        };
+        let after = match &self.after {
+            Some(cursor) => {
+                let mut values = Vec::with_capacity(cursor.values.len());
+                for (value, column) in cursor.values.iter().zip(&columns) {
+                    values.push(value.as_ref().map(|v| column.locate(v)).transpose()?);
+                }
+                Some((values, cursor.doc_address()))
+            }
+            None => None,
+        };
+        Ok(SortSegmentCollector {
+            segment_ord,
+            order: self.options.order,
+            limit: self.limit,
+            after,
+            columns,
+            docs: Vec::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(
+        &self,
+        fruits: Vec<io::Result<Vec<SortedDoc>>>,
+    ) -> tantivy::Result<Self::Fruit> {
+        let mut docs = Vec::new();
+        for fruit in fruits {
+            docs.extend(fruit?);
+        }
+        docs.sort_by(|a, b| {
+            cmp_values(&a.values, &b.values, self.options.order, SortValue::cmp)
+                .then_with(|| a.doc_address.cmp(&b.doc_address))
+        });
+        docs.truncate(self.limit);
+        Ok(docs)
+    }
+}
+
+/// Value in a segment, texts are the term ordinals of the segment which sort like them.
+#[derive(Clone, Copy)]
+enum SegmentValue {
+    Ord(u64),
+    /// A text of the cursor missing from the segment, just before the term ordinal.
+    Before(u64),
+    Number(f64),
+}
+
+impl SegmentValue {
+    fn cmp(&self, other: &SegmentValue) -> Ordering {
+        match (self, other) {
+            (SegmentValue::Ord(a), SegmentValue::Ord(b))
+            | (SegmentValue::Before(a), SegmentValue::Before(b)) => a.cmp(b),
+            // a term ordinal is never equal to a position between two of them
+            (SegmentValue::Ord(a), SegmentValue::Before(b)) => match a < b {
+                true => Ordering::Less,
+                false => Ordering::Greater,
+            },
+            (SegmentValue::Before(a), SegmentValue::Ord(b)) => match a <= b {
+                true => Ordering::Less,
+                false => Ordering::Greater,
+            },
+            (SegmentValue::Number(a), SegmentValue::Number(b)) => a.total_cmp(b),
+            (_, SegmentValue::Number(_)) => Ordering::Greater,
+            (SegmentValue::Number(_), _) => Ordering::Less,
+        }
+    }
+}
+
+enum SortColumn {
+    Score,
+    Text(Option<StrColumn>),
+    Number(Option<Column<u64>>),
+    Date(Option<Column<DateTime>>),
+    /// Cells of a row column, dates are strings of the row objects.
+    Cell {
+        numbers: Option<Column<f64>>,
+        texts: Option<StrColumn>,
+        /// Timestamps of the text term ordinals parsed so far.
+        dates: HashMap<u64, Option<f64>>,
+    },
+}
+
+impl SortColumn {
+    fn value(&mut self, doc: DocId, score: Score) -> Option<SegmentValue> {
+        match self {
+            SortColumn::Score => Some(SegmentValue::Number(score as f64)),
+            SortColumn::Text(column) => column
+                .as_ref()?
+                .term_ords(doc)
+                .next()
+                .map(SegmentValue::Ord),
+            SortColumn::Number(column) => column
+                .as_ref()?
+                .first(doc)
+                .map(|value| SegmentValue::Number(value as f64)),
+            SortColumn::Date(column) => column
+                .as_ref()?
+                .first(doc)
+                .map(|date| SegmentValue::Number(date.into_timestamp_micros() as f64)),
+            SortColumn::Cell {
+                numbers,
+                texts,
+                dates,
+            } => {
+                if let Some(number) = numbers.as_ref().and_then(|column| column.first(doc)) {
+                    return Some(SegmentValue::Number(number));
+                }
+                let texts = texts.as_ref()?;
+                let ord = texts.term_ords(doc).next()?;
+                let timestamp = dates.entry(ord).or_insert_with(|| {
+                    let mut text = String::new();
+                    texts.ord_to_str(ord, &mut text).ok()?;
+                    parse_date(&text).map(|date| date.into_timestamp_secs() as f64)
+                });
+                timestamp.map(SegmentValue::Number)
+            }
+        }
+    }
+
+    /// Value of the cursor in the segment.
+    fn locate(&self, value: &SortValue) -> io::Result<SegmentValue> {
+        let (SortColumn::Text(column), SortValue::Text(text)) = (self, value) else {
+            return Ok(match value {
+                SortValue::Number(number) => SegmentValue::Number(*number),
+                SortValue::Text(_) => SegmentValue::Before(0),
+            });
+        };
+        let Some(column) = column else {
+            return Ok(SegmentValue::Before(0));
+        };
+        if let Some(ord) = column.dictionary().term_ord(text)? {
+            return Ok(SegmentValue::Ord(ord));
+        }
+        // first term greater than the text
+        let (mut low, mut high) = (0, column.dictionary().num_terms() as u64);
+        let mut term = String::new();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            column.ord_to_str(mid, &mut term)?;
+            if term.as_str() < text.as_str() {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(SegmentValue::Before(low))
+    }
+
+    fn resolve(&self, value: SegmentValue) -> io::Result<SortValue> {
+        match (self, value) {
+            (SortColumn::Text(Some(column)), SegmentValue::Ord(ord)) => {
+                let mut text = String::new();
+                column.ord_to_str(ord, &mut text)?;
+                Ok(SortValue::Text(text))
+            }
+            (_, SegmentValue::Number(number)) => Ok(SortValue::Number(number)),
+            (_, SegmentValue::Ord(ord) | SegmentValue::Before(ord)) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("term ordinal {ord} without a text column"),
+            )),
+        }
+    }
+}
+
+pub struct SortSegmentCollector {
+    segment_ord: SegmentOrdinal,
+    order: SortOrder,
+    limit: usize,
+    after: Option<(Vec<Option<SegmentValue>>, DocAddress)>,
+    columns: Vec<SortColumn>,
+    docs: Vec<SegmentDoc>,
+}
+
+type SegmentDoc = (Vec<Option<SegmentValue>>, Score, DocId);
+
+/// By sort values, ties by document.
+fn cmp_segment_docs(a: &SegmentDoc, b: &SegmentDoc, order: SortOrder) -> Ordering {
+    cmp_values(&a.0, &b.0, order, SegmentValue::cmp).then_with(|| a.2.cmp(&b.2))
+}
+
+impl SegmentCollector for SortSegmentCollector {
+    type Fruit = io::Result<Vec<SortedDoc>>;
+
+    fn collect(&mut self, doc: DocId, score: Score) {
+        let values: Vec<_> = self
+            .columns
+            .iter_mut()
+            .map(|column| column.value(doc, score))
+            .collect();
+        if let Some((after, after_address)) = &self.after {
+            let ordering = cmp_values(&values, after, self.order, SegmentValue::cmp)
+                .then_with(|| DocAddress::new(self.segment_ord, doc).cmp(after_address));
+            if ordering != Ordering::Greater {
+                return;
+            }
+        }
+        let order = self.order;
+        push_bounded(&mut self.docs, (values, score, doc), self.limit, |a, b| {
+            cmp_segment_docs(a, b, order)
+        });
+    }
+
+    fn harvest(mut self) -> Self::Fruit {
+        let order = self.order;
+        keep_first(&mut self.docs, self.limit, |a, b| {
+            cmp_segment_docs(a, b, order)
+        });
+        let mut sorted_docs = Vec::with_capacity(self.docs.len());
+        for (values, score, doc) in self.docs {
+            let values = values
+                .into_iter()
+                .zip(&self.columns)
+                .map(|(value, column)| value.map(|value| column.resolve(value)).transpose())
+                .collect::<io::Result<_>>()?;
+            sorted_docs.push(SortedDoc {
+                values,
+                score,
+                doc_address: DocAddress::new(self.segment_ord, doc),
+            });
+        }
+        Ok(sorted_docs)
+    }
+}
+
+/// Compares the values one by one in the `order`, missing values last.
+fn cmp_values<V>(
+    a: &[Option<V>],
+    b: &[Option<V>],
+    order: SortOrder,
+    cmp: impl Fn(&V, &V) -> Ordering,
+) -> Ordering {
+    for (a, b) in a.iter().zip(b) {
+        let ordering = match (a, b) {
+            (Some(a), Some(b)) => match order {
+                SortOrder::Asc => cmp(a, b),
+                SortOrder::Desc => cmp(b, a),
+            },
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SortBy, SortOrder};
+
+    #[test]
+    fn test_parse_sort() {
+        assert_eq!(Ok(SortBy::FileName), "file_name".parse());
+        assert_eq!(
+            Ok(SortBy::Column("Due: date".to_string())),
+            "column:Due: date".parse()
+        );
+        assert!("column:".parse::<SortBy>().is_err());
+        assert!("size".parse::<SortBy>().is_err());
+        assert_eq!(SortOrder::Desc, SortBy::Relevance.default_order());
+        assert_eq!(SortOrder::Asc, SortBy::ModifiedAt.default_order());
+    }
+}
@@ -0,0 +1,231 @@
+use std::{collections::BTreeSet, error::Error, path::Path, path::PathBuf};
+
+use tantivy::{time::OffsetDateTime, DateTime};
+
+use crate::{
+    index_path,
+    index_tantivy::{FileMetadata, FileSearchIndex},
+    is_supported,
+    jobs::JobReport,
+    utils::content_hash,
+    walk::{walk_directory, SkippedEntry, WalkOptions},
+};
+
+/// Brings the index in line with the content of `root`: new and changed files are indexed,
+/// unchanged ones are left alone and the documents of vanished files are deleted.
+pub async fn sync_directory(
+    root: PathBuf,
+    walk_options: WalkOptions,
+    fsi: FileSearchIndex,
+) -> Result<JobReport, Box<dyn Error + Send + Sync>> {
+    let root = root.canonicalize()?;
+    let walk = {
+        let root = root.clone();
+        tokio::task::spawn_blocking(move || walk_directory(&root, &walk_options)).await??
+    };
+
+    fsi.index_reader.reload()?;
+    let mut indexed: BTreeSet<String> = fsi
+        .file_doc_counts()?
+        .into_keys()
+        .filter(|file_path| Path::new(file_path).starts_with(&root))
+        .collect();
+
+    let mut report = JobReport {
+        skipped: walk.skipped,
+        ..Default::default()
+    };
+    for file in walk.files {
+        if !is_supported(&file) {
+            report
+                .skipped
+                .push(SkippedEntry::new(&file, "unsupported file type"));
+            continue;
+        }
+        let file_path = match file.canonicalize() {
+            Ok(file_path) => file_path.to_string_lossy().to_string(),
+            Err(e) => {
+                tracing::error!("could not resolve {file:?}: {e:?}");
+                report
+                    .skipped
+                    .push(SkippedEntry::new(&file, format!("failed: {e}")));
+                continue;
+            }
+        };
+        if indexed.remove(&file_path) {
+            match compare(&fsi, &file, &file_path)? {
+                FileState::Unchanged => {
+                    report.files_unchanged += 1;
+                    continue;
+                }
+                FileState::Touched(metadata) => {
+                    fsi.update_metadata(&metadata).await?;
+                    report.files_unchanged += 1;
+                    continue;
+                }
+                FileState::Changed => {}
+            }
+        }
+        match index_path(file.clone(), fsi.clone(), None).await {
+            Ok(documents_added) => {
+                report.documents_added += documents_added;
+                report.files_indexed += 1;
+            }
+            Err(e) if e.is::<tantivy::TantivyError>() => return Err(e),
+            Err(e) => {
+                tracing::error!("could not index {file:?}: {e:?}");
+                report
+                    .skipped
+                    .push(SkippedEntry::new(&file, format!("failed: {e}")));
+            }
+        }
+    }
+
+    // files that are still indexed but were not walked, excluded files are kept
+    let vanished: Vec<String> = indexed
+        .into_iter()
+        .filter(|file_path| !Path::new(file_path).exists())
+        .collect();
+    fsi.delete_files(&vanished).await?;
+    report.files_deleted = vanished.len();
+
+    Ok(report)
+}
+
+/// How a walked file compares to its indexed version.
+enum FileState {
+    Unchanged,
+    /// Same content, only the modification time changed.
+    Touched(FileMetadata),
+    Changed,
+}
+
+/// Compares size and modification time first, the content hash only when they differ.
+fn compare(
+    fsi: &FileSearchIndex,
+    file: &Path,
+    file_path: &str,
+) -> Result<FileState, Box<dyn Error + Send + Sync>> {
+    let Some(indexed) = fsi.file_metadata(file_path)? else {
+        return Ok(FileState::Changed);
+    };
+    let metadata = std::fs::metadata(file)?;
+    if metadata.len() != indexed.size {
+        return Ok(FileState::Changed);
+    }
+    let modified_at = DateTime::from_utc(OffsetDateTime::from(metadata.modified()?));
+    if modified_at.into_timestamp_secs() == indexed.modified_at.into_timestamp_secs() {
+        return Ok(FileState::Unchanged);
+    }
+    if content_hash(file)? != indexed.content_hash {
+        return Ok(FileState::Changed);
+    }
+    // stored, so that the file is not hashed again on the next sync
+    Ok(FileState::Touched(FileMetadata {
+        modified_at,
+        ..indexed
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::File,
+        time::{Duration, SystemTime},
+    };
+
+    use tantivy::{collector::Count, query::RangeQuery, time::OffsetDateTime, DateTime};
+
+    use crate::{index_tantivy::CELL_NUMBER_FIELD, test_utils::test_index, walk::WalkOptions};
+
+    use super::sync_directory;
+
+    #[tokio::test]
+    async fn test_sync_directory() {
+        let fsi = test_index();
+        let dir = &fsi.dir;
+        let root = dir.join("data");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.csv"), "Name\nacme\n").unwrap();
+        std::fs::write(root.join("b.csv"), "Name\nglobex\n").unwrap();
+
+        let report = sync_directory(root.clone(), WalkOptions::default(), fsi.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            (2, 0, 0),
+            (
+                report.files_indexed,
+                report.files_unchanged,
+                report.files_deleted
+            )
+        );
+
+        std::fs::write(root.join("b.csv"), "Name\ninitech\nhooli\n").unwrap();
+        std::fs::remove_file(root.join("a.csv")).unwrap();
+        std::fs::write(root.join("c.csv"), "Name\numbrella\n").unwrap();
+        let report = sync_directory(root.clone(), WalkOptions::default(), fsi.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            (2, 0, 1),
+            (
+                report.files_indexed,
+                report.files_unchanged,
+                report.files_deleted
+            )
+        );
+
+        let report = sync_directory(root.clone(), WalkOptions::default(), fsi.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            (0, 2, 0),
+            (
+                report.files_indexed,
+                report.files_unchanged,
+                report.files_deleted
+            )
+        );
+        fsi.index_reader.reload().unwrap();
+        assert_eq!(3, fsi.index_reader.searcher().num_docs());
+    }
+
+    #[tokio::test]
+    async fn test_sync_touched_file() {
+        let fsi = test_index();
+        let dir = &fsi.dir;
+        let root = dir.join("data");
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("a.csv");
+        std::fs::write(&file, "Name,Amount\nacme,1500\n").unwrap();
+        sync_directory(root.clone(), WalkOptions::default(), fsi.clone())
+            .await
+            .unwrap();
+
+        let modified = SystemTime::now() + Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let report = sync_directory(root.clone(), WalkOptions::default(), fsi.clone())
+            .await
+            .unwrap();
+        assert_eq!((0, 1), (report.files_indexed, report.files_unchanged));
+
+        fsi.index_reader.reload().unwrap();
+        let file_path = file.canonicalize().unwrap().to_string_lossy().to_string();
+        let metadata = fsi.file_metadata(&file_path).unwrap().unwrap();
+        assert_eq!(
+            DateTime::from_utc(OffsetDateTime::from(modified)).into_timestamp_secs(),
+            metadata.modified_at.into_timestamp_secs()
+        );
+        // the values that are not stored are still indexed
+        let amount = RangeQuery::new_f64(CELL_NUMBER_FIELD.to_string(), 1000.0..2000.0);
+        let searcher = fsi.index_reader.searcher();
+        assert_eq!(1, searcher.search(&amount, &Count).unwrap());
+        assert_eq!(1, searcher.num_docs());
+    }
+}
@@ -0,0 +1,51 @@
+use std::{ops::Deref, path::PathBuf};
+
+use tantivy::DateTime;
+
+use crate::index_tantivy::{FileMetadata, FileSearchIndex};
+
+/// An empty index in `<dir>/index`, the files to index can be written in `dir`. The
+/// directory is removed once dropped.
+pub struct TestIndex {
+    pub dir: PathBuf,
+    file_search_index: FileSearchIndex,
+}
+
+impl Deref for TestIndex {
+    type Target = FileSearchIndex;
+
+    fn deref(&self) -> &FileSearchIndex {
+        &self.file_search_index
+    }
+}
+
+impl Drop for TestIndex {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn test_index() -> TestIndex {
+    let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+    std::fs::create_dir_all(&dir).unwrap();
+    let file_search_index =
+        FileSearchIndex::new(&dir.join("index").to_string_lossy(), 15_000_000, None).unwrap();
+    TestIndex {
+        dir,
+        file_search_index,
+    }
+}
+
+/// Metadata of a csv file at `file_path`, which doesn't have to exist.
+pub fn metadata(file_path: &str) -> FileMetadata {
+    let (parent_dir, file_name) = file_path.rsplit_once('/').unwrap();
+    FileMetadata {
+        file_path: file_path.to_string(),
+        file_name: file_name.to_string(),
+        parent_dir: parent_dir.to_string(),
+        file_type: "csv".to_string(),
+        size: 0,
+        modified_at: DateTime::from_timestamp_secs(0),
+        content_hash: String::new(),
+    }
+}
@@ -1,3 +1,14 @@
+use std::{cmp::Ordering, fs::File, path::Path};
+
+use chrono::{Months, NaiveDate, NaiveDateTime};
+use serde_json::{Map, Number, Value};
+use sha2::{Digest, Sha256};
+use tantivy::{
+    time::format_description::well_known::Rfc3339,
+    tokenizer::{AsciiFoldingFilter, LowerCaser, RawTokenizer, TextAnalyzer},
+    DateTime,
+};
+
 pub type Column = usize;
 pub type Row = usize;
 
@@ -27,3 +38,283 @@ pub fn convert_row_column_to_letter(mut row: Row, mut column: Column) -> String
 
     res
 }
+
+/// Letter of a column, e.g. `D` for the fourth column.
+pub fn convert_column_to_letter(column: Column) -> String {
+    convert_row_column_to_letter(0, column)
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .to_string()
+}
+
+/// Header of a column, its letter when the header row has no label for it.
+pub fn column_header(labels: &[String], column: Column) -> String {
+    match labels.get(column).map(|l| l.trim()) {
+        Some(label) if !label.is_empty() => label.to_string(),
+        _ => convert_column_to_letter(column),
+    }
+}
+
+/// Adds a cell to a row keyed by header. A header used twice is suffixed by the letter
+/// of the column, e.g. `Amount (D)`.
+pub fn insert_cell(row: &mut Map<String, Value>, header: &str, column: Column, value: Value) {
+    let key = if row.contains_key(header) {
+        format!("{header} ({})", convert_column_to_letter(column))
+    } else {
+        header.to_string()
+    };
+    row.insert(key, value);
+}
+
+/// Pushes `item` to a buffer of the first `limit` items by `compare`, e.g. the best hits
+/// of a segment. Sorting on every push would be quadratic, so the buffer is only sorted
+/// and truncated once it holds twice the limit; `keep_first` completes it.
+pub fn push_bounded<T>(
+    items: &mut Vec<T>,
+    item: T,
+    limit: usize,
+    compare: impl FnMut(&T, &T) -> Ordering,
+) {
+    items.push(item);
+    if items.len() >= 2 * limit.max(1) {
+        keep_first(items, limit, compare);
+    }
+}
+
+/// Sorts `items` by `compare` and keeps the first `limit` of them.
+pub fn keep_first<T>(items: &mut Vec<T>, limit: usize, compare: impl FnMut(&T, &T) -> Ordering) {
+    items.sort_by(compare);
+    items.truncate(limit);
+}
+
+/// Hex encoded SHA-256 of the content of the file at `path`.
+pub fn content_hash(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Converts a glob pattern (`*` and `?` wildcards) to a regex matching the whole term.
+pub fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::with_capacity(pattern.len() * 2);
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex
+}
+
+/// Formats a date as RFC 3339, like tantivy does for stored dates.
+pub fn format_date(date: DateTime) -> String {
+    date.into_utc().format(&Rfc3339).unwrap_or_default()
+}
+
+/// Levenshtein distance between two strings. With `transpositions`, swapping two adjacent
+/// characters counts as a single edit like the fuzzy term query does.
+pub fn edit_distance(a: &str, b: &str, transpositions: bool) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if transpositions && i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+/// Typed value of a spreadsheet cell, numbers and dates can be range queried.
+pub enum CellValue {
+    Number(f64),
+    Date(DateTime),
+    Text(String),
+}
+
+impl CellValue {
+    /// Infers the type of a textual cell, e.g. from a csv file.
+    pub fn parse(text: &str) -> CellValue {
+        if let Some(number) = parse_number(text) {
+            CellValue::Number(number)
+        } else if let Some(date) = parse_date(text) {
+            CellValue::Date(date)
+        } else {
+            CellValue::Text(text.to_string())
+        }
+    }
+
+    /// Value of the cell in the `row_values` object. Dates are RFC 3339 strings, which tantivy
+    /// indexes as dates.
+    pub fn to_json(&self) -> Value {
+        match self {
+            CellValue::Number(number) => Number::from_f64(*number)
+                .map(Value::Number)
+                .unwrap_or(Value::Null),
+            CellValue::Date(date) => Value::String(format_date(*date)),
+            CellValue::Text(text) => Value::String(text.clone()),
+        }
+    }
+}
+
+/// Parses a finite number, ignoring surrounding whitespace.
+pub fn parse_number(text: &str) -> Option<f64> {
+    text.trim().parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+/// Date of a naive date time, taken as UTC.
+pub fn naive_to_date(date_time: NaiveDateTime) -> DateTime {
+    DateTime::from_timestamp_secs(date_time.and_utc().timestamp())
+}
+
+/// Date of a naive date time taken as UTC, if it fits in the nanosecond timestamps of
+/// tantivy, from 1677 to 2262.
+fn checked_naive_to_date(date_time: NaiveDateTime) -> Option<DateTime> {
+    let seconds = date_time.and_utc().timestamp();
+    seconds.checked_mul(1_000_000_000)?;
+    Some(DateTime::from_timestamp_secs(seconds))
+}
+
+/// Parses an RFC 3339 date, or a `2023-10-05` / `2023-10-05 14:30:00` date taken as UTC.
+pub fn parse_date(text: &str) -> Option<DateTime> {
+    let text = text.trim();
+    if let Ok(date) = tantivy::time::OffsetDateTime::parse(text, &Rfc3339) {
+        return Some(DateTime::from_utc(date));
+    }
+    if let Ok(date_time) = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S") {
+        return checked_naive_to_date(date_time);
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(checked_naive_to_date)
+}
+
+/// Parses a period as its start and (exclusive) end: a year `2023`, a quarter `2023-Q4`,
+/// a month `2023-10`, a day `2023-10-05` or an instant.
+pub fn parse_period(text: &str) -> Option<(DateTime, DateTime)> {
+    let text = text.trim();
+    let period = |year: &str, month: u32, months: u32| {
+        let start = NaiveDate::from_ymd_opt(year.parse().ok()?, month, 1)?;
+        let end = start.checked_add_months(Months::new(months))?;
+        Some((
+            checked_naive_to_date(start.and_hms_opt(0, 0, 0)?)?,
+            checked_naive_to_date(end.and_hms_opt(0, 0, 0)?)?,
+        ))
+    };
+    match text.split_once('-') {
+        None if text.len() == 4 => period(text, 1, 12),
+        Some((year, quarter)) if quarter.starts_with(['Q', 'q']) => {
+            let quarter: u32 = quarter[1..].parse().ok().filter(|q| (1..=4).contains(q))?;
+            period(year, quarter * 3 - 2, 3)
+        }
+        Some((year, month)) if month.len() == 2 => period(year, month.parse().ok()?, 1),
+        _ => {
+            let date = parse_date(text)?;
+            let is_day = NaiveDate::parse_from_str(text, "%Y-%m-%d").is_ok();
+            let seconds = if is_day { 86_400 } else { 1 };
+            let end = DateTime::from_timestamp_secs(date.into_timestamp_secs() + seconds);
+            Some((date, end))
+        }
+    }
+}
+
+/// Lowercases `text` and folds its accented characters to ASCII, e.g. `Société` to `societe`.
+pub fn fold(text: &str) -> String {
+    let mut analyzer = TextAnalyzer::builder(RawTokenizer::default())
+        .filter(LowerCaser)
+        .filter(AsciiFoldingFilter)
+        .build();
+    let mut token_stream = analyzer.token_stream(text);
+    match token_stream.next() {
+        Some(token) => token.text.clone(),
+        None => String::new(),
+    }
+}
+
+/// Folds the literal characters of a regex pattern, escape sequences such as `\S` or
+/// `\p{Lu}` are kept as is.
+pub fn fold_pattern(pattern: &str) -> String {
+    let mut folded = String::with_capacity(pattern.len());
+    let mut literal = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            literal.push(c);
+            continue;
+        }
+        folded += &fold(&literal);
+        literal.clear();
+        folded.push(c);
+        if let Some(escaped) = chars.next() {
+            folded.push(escaped);
+        }
+        if chars.peek() == Some(&'{') {
+            for c in chars.by_ref() {
+                folded.push(c);
+                if c == '}' {
+                    break;
+                }
+            }
+        }
+    }
+    folded + &fold(&literal)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fold, fold_pattern, format_date, keep_first, parse_period, push_bounded};
+
+    #[test]
+    fn test_push_bounded() {
+        let mut items = vec![];
+        for item in [5, 3, 8, 1, 9, 2, 7] {
+            push_bounded(&mut items, item, 2, |a: &i32, b| b.cmp(a));
+            assert!(items.len() < 4);
+        }
+        keep_first(&mut items, 2, |a, b| b.cmp(a));
+        assert_eq!(vec![9, 8], items);
+    }
+
+    #[test]
+    fn test_fold() {
+        assert_eq!("societe generale", fold("Société Générale"));
+        assert_eq!("ijsselmeer, 1.5", fold("IJsselmeer, 1.5"));
+        assert_eq!("soci.t.*", fold_pattern("SOCI.T.*"));
+        assert_eq!("c[ae]fe\\S+\\p{Lu}", fold_pattern("C[AÉ]fé\\S+\\p{Lu}"));
+    }
+
+    #[test]
+    fn test_parse_period() {
+        let period =
+            |text| parse_period(text).map(|(start, end)| (format_date(start), format_date(end)));
+        let expected = |start: &str, end: &str| Some((start.to_string(), end.to_string()));
+        assert_eq!(
+            expected("2023-10-01T00:00:00Z", "2024-01-01T00:00:00Z"),
+            period("2023-Q4")
+        );
+        assert_eq!(
+            expected("2023-01-01T00:00:00Z", "2024-01-01T00:00:00Z"),
+            period("2023")
+        );
+        assert_eq!(
+            expected("2023-02-01T00:00:00Z", "2023-03-01T00:00:00Z"),
+            period("2023-02")
+        );
+        assert_eq!(
+            expected("2023-02-28T00:00:00Z", "2023-03-01T00:00:00Z"),
+            period("2023-02-28")
+        );
+        assert_eq!(None, period("2023-Q5"));
+        assert_eq!(None, period("acme"));
+        assert_eq!(None, period("5000"));
+    }
+}
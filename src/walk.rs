@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Serialize;
+use walkdir::{DirEntry, WalkDir};
+
+#[derive(Default, Clone)]
+pub struct WalkOptions {
+    /// Maximum depth to descend, `1` only visits the entries of the root directory.
+    pub max_depth: Option<usize>,
+    /// Glob patterns a file must match to be kept. Keeps everything when empty.
+    pub include: Vec<String>,
+    /// Glob patterns of files and directories to skip.
+    pub exclude: Vec<String>,
+    pub follow_symlinks: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SkippedEntry {
+    pub path: String,
+    pub reason: String,
+}
+
+impl SkippedEntry {
+    pub fn new(path: &Path, reason: impl Into<String>) -> SkippedEntry {
+        SkippedEntry {
+            path: path.to_string_lossy().to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DirectoryWalk {
+    pub files: Vec<PathBuf>,
+    pub skipped: Vec<SkippedEntry>,
+}
+
+/// Matches a path either by its file name or by its path relative to the walked root,
+/// so `~$*.xlsx` and `.git` work as well as `archive/**`.
+pub struct PathMatcher {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathMatcher {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<PathMatcher, globset::Error> {
+        Ok(PathMatcher {
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(build_glob_set(include)?)
+            },
+            exclude: build_glob_set(exclude)?,
+        })
+    }
+
+    pub fn is_excluded(&self, root: &Path, path: &Path) -> bool {
+        matches_glob_set(&self.exclude, root, path)
+    }
+
+    pub fn is_included(&self, root: &Path, path: &Path) -> bool {
+        self.include
+            .as_ref()
+            .map(|include| matches_glob_set(include, root, path))
+            .unwrap_or(true)
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+fn matches_glob_set(glob_set: &GlobSet, root: &Path, path: &Path) -> bool {
+    path.file_name().is_some_and(|name| glob_set.is_match(name))
+        || path
+            .strip_prefix(root)
+            .is_ok_and(|relative| glob_set.is_match(relative))
+}
+
+/// Recursively collects the files under `root`, reporting every entry that was left out
+/// instead of failing on it.
+pub fn walk_directory(root: &Path, options: &WalkOptions) -> Result<DirectoryWalk, globset::Error> {
+    let matcher = PathMatcher::new(&options.include, &options.exclude)?;
+    let mut walk = DirectoryWalk::default();
+
+    let mut walk_dir = WalkDir::new(root)
+        .min_depth(1)
+        .follow_links(options.follow_symlinks)
+        .sort_by_file_name();
+    if let Some(max_depth) = options.max_depth {
+        walk_dir = walk_dir.max_depth(max_depth);
+    }
+
+    let mut entries = walk_dir.into_iter();
+    while let Some(entry) = entries.next() {
+        let entry: DirEntry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+                walk.skipped.push(SkippedEntry::new(&path, e.to_string()));
+                continue;
+            }
+        };
+        let path = entry.path();
+        if matcher.is_excluded(root, path) {
+            if entry.file_type().is_dir() {
+                entries.skip_current_dir();
+            }
+            walk.skipped.push(SkippedEntry::new(path, "excluded"));
+        } else if entry.path_is_symlink() && !options.follow_symlinks {
+            walk.skipped.push(SkippedEntry::new(path, "symbolic link"));
+        } else if entry.file_type().is_file() {
+            if matcher.is_included(root, path) {
+                walk.files.push(path.to_path_buf());
+            } else {
+                walk.skipped.push(SkippedEntry::new(path, "not included"));
+            }
+        }
+    }
+    Ok(walk)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{walk_directory, WalkOptions};
+
+    #[test]
+    fn test_walk_directory() {
+        let root = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(root.join("2023/archive")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        for file in [
+            "report.xlsx",
+            "~$report.xlsx",
+            "2023/data.csv",
+            "2023/archive/old.csv",
+            ".git/config",
+        ] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let options = WalkOptions {
+            exclude: vec!["~$*".into(), ".git".into()],
+            ..Default::default()
+        };
+        let walk = walk_directory(&root, &options).unwrap();
+        assert_eq!(
+            vec![
+                root.join("2023/archive/old.csv"),
+                root.join("2023/data.csv"),
+                root.join("report.xlsx")
+            ],
+            walk.files
+        );
+        assert_eq!(2, walk.skipped.len());
+
+        let options = WalkOptions {
+            max_depth: Some(2),
+            include: vec!["*.csv".into()],
+            ..options
+        };
+        let walk = walk_directory(&root, &options).unwrap();
+        assert_eq!(vec![root.join("2023/data.csv")], walk.files);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use notify_debouncer_mini::{
+    new_debouncer,
+    notify::{RecommendedWatcher, RecursiveMode},
+    DebounceEventResult, DebouncedEventKind, Debouncer,
+};
+
+use crate::{
+    index_path,
+    index_tantivy::SharedIndex,
+    is_supported,
+    jobs::{JobKind, JobRegistry, JobReport},
+    walk::{walk_directory, PathMatcher, SkippedEntry, WalkOptions},
+};
+
+pub struct WatchConfig {
+    pub directories: Vec<PathBuf>,
+    /// How long a path must stay untouched before it is indexed.
+    pub debounce: Duration,
+    pub exclude: Vec<String>,
+}
+
+/// Watches the configured directories and keeps the index in sync with their content.
+/// Watching stops when the returned debouncer is dropped.
+pub fn watch(
+    config: WatchConfig,
+    index: SharedIndex,
+    jobs: JobRegistry,
+) -> Result<Debouncer<RecommendedWatcher>, Box<dyn Error + Send + Sync>> {
+    let matcher = PathMatcher::new(&[], &config.exclude)?;
+    let roots = config
+        .directories
+        .iter()
+        .map(|d| d.canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut debouncer = new_debouncer(config.debounce, move |res: DebounceEventResult| {
+        let _ = tx.send(res);
+    })?;
+    for root in &roots {
+        debouncer.watcher().watch(root, RecursiveMode::Recursive)?;
+        tracing::info!("watching {root:?}");
+    }
+
+    tokio::spawn(async move {
+        while let Some(res) = rx.recv().await {
+            let events = match res {
+                Ok(events) => events,
+                Err(e) => {
+                    tracing::error!("watch error: {e:?}");
+                    continue;
+                }
+            };
+            for event in events {
+                // the file is still being written, it will show up again once stable
+                if event.kind == DebouncedEventKind::AnyContinuous {
+                    continue;
+                }
+                let Some(root) = roots.iter().find(|r| event.path.starts_with(r)) else {
+                    continue;
+                };
+                if is_excluded(&matcher, root, &event.path) {
+                    continue;
+                }
+                sync_path(event.path, &config.exclude, &index, &jobs).await;
+            }
+        }
+    });
+    Ok(debouncer)
+}
+
+fn is_excluded(matcher: &PathMatcher, root: &Path, path: &Path) -> bool {
+    path.ancestors()
+        .take_while(|p| *p != root)
+        .any(|p| matcher.is_excluded(root, p))
+}
+
+/// Hands the changes of `path` to the job registry, the event loop never waits for the
+/// index or a walk.
+async fn sync_path(path: PathBuf, exclude: &[String], index: &SharedIndex, jobs: &JobRegistry) {
+    let job_path = path.to_string_lossy().to_string();
+    if path.is_dir() {
+        let walk_options = WalkOptions {
+            exclude: exclude.to_vec(),
+            ..Default::default()
+        };
+        jobs.spawn_when(JobKind::Watch, job_path, index.write(), |fsi| async move {
+            let walk =
+                tokio::task::spawn_blocking(move || walk_directory(&path, &walk_options)).await??;
+            let mut report = JobReport {
+                skipped: walk.skipped,
+                ..Default::default()
+            };
+            for file in walk.files.into_iter().filter(|f| is_supported(f)) {
+                match index_path(file.clone(), fsi.clone(), None).await {
+                    Ok(documents_added) => {
+                        report.documents_added += documents_added;
+                        report.files_indexed += 1;
+                    }
+                    Err(e) => {
+                        tracing::error!("could not index {file:?}: {e:?}");
+                        report
+                            .skipped
+                            .push(SkippedEntry::new(&file, format!("failed: {e}")));
+                    }
+                }
+            }
+            Ok(report)
+        })
+        .await;
+    } else if path.is_file() {
+        if !is_supported(&path) {
+            return;
+        }
+        jobs.spawn_when(JobKind::Watch, job_path, index.write(), |fsi| async move {
+            let documents_added = index_path(path, fsi.clone(), None).await?;
+            Ok(JobReport {
+                documents_added,
+                ..Default::default()
+            })
+        })
+        .await;
+    } else {
+        tracing::info!("{path:?} removed, deleting its documents");
+        jobs.spawn_when(JobKind::Watch, job_path, index.write(), |fsi| async move {
+            let count = fsi.delete_path(&path.to_string_lossy()).await?;
+            tracing::info!("{count} documents of {path:?} deleted");
+            Ok(JobReport::default())
+        })
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{
+        index_tantivy::{QueryType, SharedIndex},
+        jobs::{JobRegistry, JobStatus},
+        test_utils::test_index,
+    };
+
+    use super::{watch, WatchConfig};
+
+    /// Hits of `q` once the index satisfies `expected`, waiting for the watcher.
+    async fn wait_for_hits(index: &SharedIndex, q: &str, expected: usize) -> usize {
+        let mut total_hits = 0;
+        for _ in 0..100 {
+            let fsi = index.current();
+            fsi.index_reader.reload().unwrap();
+            total_hits = fsi
+                .search(1, 10, q, &QueryType::TermQuery, &Default::default())
+                .unwrap()
+                .total_hits;
+            if total_hits == expected {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        total_hits
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let fsi = test_index();
+        let dir = &fsi.dir;
+        let root = dir.join("data");
+        std::fs::create_dir_all(&root).unwrap();
+        let index = SharedIndex::new(fsi.clone());
+        let config = WatchConfig {
+            directories: vec![root.clone()],
+            debounce: Duration::from_millis(100),
+            exclude: vec![],
+        };
+        let _debouncer = watch(config, index.clone(), JobRegistry::default()).unwrap();
+
+        std::fs::write(root.join("a.csv"), "Name\nacme\n").unwrap();
+        assert_eq!(1, wait_for_hits(&index, "acme", 1).await);
+
+        std::fs::write(root.join("a.csv"), "Name\nglobex\n").unwrap();
+        assert_eq!(1, wait_for_hits(&index, "globex", 1).await);
+        assert_eq!(0, wait_for_hits(&index, "acme", 0).await);
+
+        std::fs::remove_file(root.join("a.csv")).unwrap();
+        assert_eq!(0, wait_for_hits(&index, "globex", 0).await);
+
+        // a directory moved in is walked
+        std::fs::create_dir_all(dir.join("staging")).unwrap();
+        std::fs::write(dir.join("staging/b.csv"), "Name\ninitech\n").unwrap();
+        std::fs::rename(dir.join("staging"), root.join("sub")).unwrap();
+        assert_eq!(1, wait_for_hits(&index, "initech", 1).await);
+    }
+
+    #[tokio::test]
+    async fn test_watch_during_rebuild() {
+        let fsi = test_index();
+        let dir = &fsi.dir;
+        let root = dir.join("data");
+        std::fs::create_dir_all(&root).unwrap();
+        let index = SharedIndex::new(fsi.clone());
+        let jobs = JobRegistry::default();
+        let config = WatchConfig {
+            directories: vec![root.clone()],
+            debounce: Duration::from_millis(100),
+            exclude: vec![],
+        };
+        let _debouncer = watch(config, index.clone(), jobs.clone()).unwrap();
+
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+        let rebuild = tokio::spawn({
+            let index = index.clone();
+            async move {
+                index
+                    .rebuild(|_| async move {
+                        started_tx.send(()).unwrap();
+                        done_rx.await?;
+                        Ok(())
+                    })
+                    .await
+            }
+        });
+        started_rx.await.unwrap();
+
+        // every event is queued while the rebuild holds the index
+        std::fs::write(root.join("a.csv"), "Name\nacme\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        std::fs::write(root.join("b.csv"), "Name\nglobex\n").unwrap();
+        let expected: Vec<_> = ["a.csv", "b.csv"]
+            .iter()
+            .map(|f| {
+                root.canonicalize()
+                    .unwrap()
+                    .join(f)
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect();
+        let mut queued = vec![];
+        for _ in 0..100 {
+            queued = jobs
+                .list()
+                .await
+                .into_iter()
+                .filter(|j| j.status == JobStatus::Queued)
+                .map(|j| j.path)
+                .collect();
+            if expected.iter().all(|p| queued.contains(p)) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(expected.iter().all(|p| queued.contains(p)), "{queued:?}");
+
+        done_tx.send(()).unwrap();
+        rebuild.await.unwrap().unwrap();
+        assert_eq!(1, wait_for_hits(&index, "acme", 1).await);
+        assert_eq!(1, wait_for_hits(&index, "globex", 1).await);
+    }
+}